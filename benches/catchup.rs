@@ -0,0 +1,192 @@
+//! Catch-up cost: one `SHOW CHANGES` scan per agent vs the coalition's shared
+//! changefeed reader.
+//!
+//! For each coalition size `N`, every agent gets `MESSAGES_PER_AGENT` messages
+//! while offline. The same backlog is then read two ways:
+//!
+//! - **per-agent scan** — what every listen loop used to do: page the whole
+//!   `message` changefeed from its own cursor and drop changes not addressed to
+//!   it. `N` full scans.
+//! - **shared scan** — what the coalition's reader does: page the changefeed
+//!   once from the lowest of the agents' cursors and keep changes addressed to
+//!   any of them. One scan, demultiplexed by `out`.
+//!
+//! Both are the same [`scan`] with different owner sets, timed the same way
+//! and with their changesets counted as they are read — the DB-side measure,
+//! `N × log` vs `log`. Neither includes coalition startup. Afterwards the
+//! coalition is restarted to check that its reader really delivers the whole
+//! backlog.
+//!
+//! # How to run
+//! ```text
+//! SDB__ENDPOINT=mem:// cargo bench --bench catchup
+//! ```
+//! Without `SDB__ENDPOINT` it starts the usual Docker container instead.
+
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+use surrealdb::Surreal;
+use surrealdb::engine::any;
+use surrealdb_types::{RecordId, SurrealValue, Value};
+use tokio::time::{Duration, Instant, sleep, timeout};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+use surrealdb_live_message::message::MESSAGE_TABLE;
+use surrealdb_live_message::subsystems::agents::{AGENT_TABLE, Agent, CURSOR_TABLE, Coalition};
+use surrealdb_live_message::subsystems::sdb::{self, SurrealDBWrapper};
+
+const COALITION_SIZES: [usize; 3] = [1, 10, 50];
+const MESSAGES_PER_AGENT: usize = 100;
+/// Same page size as the library's catch-up.
+const PAGE: usize = 1000;
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone, Serialize, Deserialize, SurrealValue)]
+struct Tick {
+    seq: i64,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let token = CancellationToken::new();
+    let tracker = TaskTracker::new();
+    let sdb_token = token.child_token();
+    let mut sdb_handle = tracker.spawn(async move { sdb::sdb_task(sdb_token).await });
+    tracker.close();
+
+    tokio::select! {
+        result = SurrealDBWrapper::wait_until_ready() => {
+            result.context("SurrealDB ready signal failed")?;
+        }
+        result = &mut sdb_handle => {
+            anyhow::bail!("sdb_task exited during startup: {result:?}");
+        }
+        _ = sleep(STARTUP_TIMEOUT) => {
+            token.cancel();
+            tracker.wait().await;
+            anyhow::bail!("SurrealDB not ready within {STARTUP_TIMEOUT:?}");
+        }
+    }
+    let db = &SurrealDBWrapper::connection().await?;
+    let source = Agent::new("bench_src").await?;
+
+    println!(
+        "{:>6} {:>10} {:>18} {:>12} {:>18} {:>12}",
+        "agents", "messages", "per-agent reads", "per-agent", "shared reads", "shared"
+    );
+    for n in COALITION_SIZES {
+        let names: Vec<String> = (0..n).map(|i| format!("bench_n{n}_{i}")).collect();
+
+        // Register the agents (persisting "start from now" cursors), then
+        // queue the backlog while they are offline.
+        Coalition::<Tick>::new(names.clone())
+            .await?
+            .shutdown()
+            .await;
+        for seq in 0..MESSAGES_PER_AGENT {
+            for name in &names {
+                source.send(name, Tick { seq: seq as i64 }).await?;
+            }
+        }
+        let total = n * MESSAGES_PER_AGENT;
+
+        // Baseline: one full scan per agent. Read-only — cursors stay put.
+        let started = Instant::now();
+        let mut per_agent_reads = 0;
+        let mut found = 0;
+        for name in &names {
+            let (reads, owned) = scan(db, std::slice::from_ref(name)).await?;
+            per_agent_reads += reads;
+            found += owned;
+        }
+        let per_agent = started.elapsed();
+        anyhow::ensure!(found == total, "per-agent scan found {found}/{total}");
+
+        // Shared: one scan for the whole coalition.
+        let started = Instant::now();
+        let (shared_reads, found) = scan(db, &names).await?;
+        let shared = started.elapsed();
+        anyhow::ensure!(found == total, "shared scan found {found}/{total}");
+
+        // The real reader: restart and wait for the whole backlog on the bus.
+        let coalition = Coalition::<Tick>::new(names).await?;
+        let inbox = coalition.inbox();
+        timeout(DRAIN_TIMEOUT, async {
+            for _ in 0..total {
+                inbox.recv().await?;
+            }
+            anyhow::Ok(())
+        })
+        .await
+        .context("shared reader did not deliver the backlog in time")??;
+        coalition.shutdown().await;
+
+        println!(
+            "{n:>6} {total:>10} {per_agent_reads:>18} {per_agent:>12.2?} {shared_reads:>18} {shared:>12.2?}"
+        );
+    }
+
+    token.cancel();
+    tracker.wait().await;
+    Ok(())
+}
+
+/// Catch-up for `names`: page `SHOW CHANGES` from the lowest of their
+/// persisted cursors to the live edge, keeping only changes addressed to one
+/// of them. Returns `(changesets read, messages owned)`.
+async fn scan(db: &Surreal<any::Any>, names: &[String]) -> anyhow::Result<(usize, usize)> {
+    let mut owners = Vec::with_capacity(names.len());
+    let mut cursor = i64::MAX;
+    for name in names {
+        let row: Option<Value> = db.select((CURSOR_TABLE, name.as_str())).await?;
+        cursor = cursor.min(row.as_ref().and_then(versionstamp_of).unwrap_or(0));
+        owners.push(Value::RecordId(RecordId::new(AGENT_TABLE, name.as_str())));
+    }
+    let (mut reads, mut owned) = (0, 0);
+    loop {
+        let q = format!("SHOW CHANGES FOR TABLE {MESSAGE_TABLE} SINCE {cursor} LIMIT {PAGE}");
+        let v: Value = db.query(&q).await?.take(0)?;
+        let Value::Array(changesets) = v else { break };
+        reads += changesets.len();
+        for changeset in changesets.iter() {
+            let Value::Object(obj) = changeset else {
+                continue;
+            };
+            if let Some(vs) = versionstamp_of(changeset) {
+                cursor = cursor.max(vs + 1);
+            }
+            let Some(Value::Array(changes)) = obj.get("changes") else {
+                continue;
+            };
+            owned += changes
+                .iter()
+                .filter(|change| {
+                    let Value::Object(op) = change else {
+                        return false;
+                    };
+                    matches!(
+                        op.get("create").or_else(|| op.get("update")),
+                        Some(Value::Object(record))
+                            if record.get("out").is_some_and(|out| owners.contains(out))
+                    )
+                })
+                .count();
+        }
+        if changesets.len() < PAGE {
+            break;
+        }
+    }
+    Ok((reads, owned))
+}
+
+fn versionstamp_of(v: &Value) -> Option<i64> {
+    match v {
+        Value::Object(o) => match o.get("versionstamp") {
+            Some(Value::Number(n)) => n.to_int(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
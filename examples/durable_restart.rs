@@ -26,6 +26,7 @@
 //!
 //! # Prerequisites
 //! Docker must be running. The library spins up a SurrealDB container on start.
+//! Without Docker, run embedded instead: `SDB__ENDPOINT=mem:// cargo run --example durable_restart`.
 
 use anyhow::Context as _;
 use serde::{Deserialize, Serialize};
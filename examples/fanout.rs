@@ -18,7 +18,7 @@
 //! ```
 //!
 //! # Prerequisites
-//! Docker must be running.
+//! Docker must be running, or run embedded with `SDB__ENDPOINT=mem://`.
 
 use std::collections::HashMap;
 
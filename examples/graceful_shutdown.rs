@@ -41,7 +41,8 @@
 //!
 //! # Prerequisites
 //! Docker must be running. The library spins up a SurrealDB container on start
-//! and tears it down on shutdown.
+//! and tears it down on shutdown. Without Docker, run embedded instead:
+//! `SDB__ENDPOINT=mem:// cargo run --example graceful_shutdown`.
 
 use anyhow::Context as _;
 use serde::{Deserialize, Serialize};
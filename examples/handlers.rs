@@ -0,0 +1,133 @@
+//! Managed dispatch: register an `AgentHandler` instead of hand-rolling a
+//! receive loop.
+//!
+//! Every other example drains `inbox()` with its own `while let Ok(d) = …`
+//! loop and spawning glue. Here the coalition owns that loop:
+//!
+//! - `Counter` is registered for `bob` with a concurrency of 4. Each delivery
+//!   runs on its own task under the coalition's `TaskTracker`; the handler
+//!   replies through its `AgentContext`.
+//! - `alice` fires a handful of `request`s at `bob` concurrently and awaits
+//!   each reply.
+//! - `coalition.shutdown()` stops the dispatch loop and drains in-flight
+//!   handlers with the usual cancel → close → wait.
+//!
+//! Self-terminating.
+//!
+//! # How to run
+//! ```text
+//! cargo run --example handlers
+//! ```
+//!
+//! # Prerequisites
+//! Docker must be running, or run embedded with `SDB__ENDPOINT=mem://`.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+use surrealdb_types::SurrealValue;
+use tokio::time::{Duration, sleep};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+use surrealdb_live_message::logger;
+use surrealdb_live_message::subsystems::agents::{Coalition, Delivery};
+use surrealdb_live_message::subsystems::dispatch::{AgentContext, AgentHandler, HandlerError};
+use surrealdb_live_message::subsystems::sdb::{self, SurrealDBWrapper};
+
+const ALICE: &str = "alice";
+const BOB: &str = "bob";
+const N_REQUESTS: usize = 8;
+const BOB_CONCURRENCY: usize = 4;
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+const REPLY_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Serialize, Deserialize, SurrealValue)]
+pub struct Job {
+    pub seq: i64,
+}
+
+/// Counts the jobs it has seen and replies with the running total.
+#[derive(Default)]
+struct Counter {
+    seen: AtomicUsize,
+}
+
+impl AgentHandler<Job> for Counter {
+    async fn handle(&self, ctx: AgentContext, delivery: Delivery<Job>) -> Result<(), HandlerError> {
+        let seen = self.seen.fetch_add(1, Ordering::Relaxed) + 1;
+        tracing::info!(agent = %ctx.agent().name, seq = delivery.message.payload.seq, seen, "handling");
+        ctx.reply(&delivery.message, Job { seq: seen as i64 })
+            .await?;
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    logger::setup();
+
+    let token = CancellationToken::new();
+    let tracker = TaskTracker::new();
+
+    let sdb_token = token.child_token();
+    let mut sdb_handle = tracker.spawn(async move { sdb::sdb_task(sdb_token).await });
+    tracker.close();
+
+    // Startup supervision — DB ready, sdb exit, or hard timeout.
+    tokio::select! {
+        result = SurrealDBWrapper::wait_until_ready() => {
+            result.context("SurrealDB ready signal failed")?;
+        }
+        result = &mut sdb_handle => {
+            token.cancel();
+            anyhow::bail!("sdb_task exited during startup: {result:?}");
+        }
+        _ = sleep(STARTUP_TIMEOUT) => {
+            token.cancel();
+            tracker.wait().await;
+            anyhow::bail!("SurrealDB not ready within {STARTUP_TIMEOUT:?}");
+        }
+    }
+    tracing::info!("SurrealDB ready.");
+
+    let coalition = Coalition::<Job>::new(vec![ALICE.to_string(), BOB.to_string()])
+        .await
+        .context("failed to build coalition")?;
+    coalition
+        .register_handler_with_concurrency(BOB, Counter::default(), BOB_CONCURRENCY)
+        .await
+        .context("register bob's handler")?;
+
+    let alice = coalition
+        .agent(ALICE)
+        .await
+        .context("alice missing from coalition")?;
+    let requests = (0..N_REQUESTS).map(|seq| {
+        let alice = alice.clone();
+        async move {
+            alice
+                .request::<Job, Job>(BOB, Job { seq: seq as i64 }, REPLY_TIMEOUT)
+                .await
+        }
+    });
+    for (seq, reply) in futures::future::join_all(requests)
+        .await
+        .into_iter()
+        .enumerate()
+    {
+        match reply {
+            Ok(r) => tracing::info!(seq, total = r.payload.seq, "reply"),
+            Err(e) => tracing::error!(seq, "request failed: {e}"),
+        }
+    }
+
+    // Standard shutdown order: coalition first (drains bob's handlers), then
+    // the sdb token, then drain.
+    coalition.shutdown().await;
+    token.cancel();
+    tracker.wait().await;
+    tracing::info!("stopped cleanly");
+    Ok(())
+}
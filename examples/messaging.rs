@@ -1,16 +1,16 @@
-//! Request/response round-trip over the coalition delivery bus.
+//! Request/response round-trip with `Agent::request` / `Agent::reply`.
 //!
 //! The two shutdown examples only show a one-way `alice → bob` greeting. This
 //! example shows the actual messaging contract: a reply travelling back to the
-//! original sender, both legs observed off a single [`Coalition::inbox`].
+//! original sender and resolving the request that caused it.
 //!
-//! - `alice` sends a `Chat { from: "alice", body: "ping" }` to `bob`.
-//! - A consumer task drains the bus. When it sees a delivery addressed to
-//!   `bob`, it has `bob` reply to `payload.from` — the sender name is carried
-//!   *in the payload*, which is cleaner than extracting it from the edge's
-//!   `in` `RecordId`.
-//! - When the reply lands back on `alice`, the round-trip is complete and the
-//!   example shuts down.
+//! - `alice` calls `alice.request::<Chat, Chat>(BOB, ping, timeout)`. The
+//!   edge is stamped with a correlation id.
+//! - A consumer task drains the bus. Bob's deliveries arrive as usual; for each
+//!   one it calls `bob.reply(&d.message, …)`, which addresses the edge's sender
+//!   and echoes the correlation id — no sender name in the payload needed.
+//! - The reply never touches the inbox: alice's listen loop routes it straight
+//!   back to the pending `request` future, which returns it.
 //!
 //! Self-terminating: runs to completion, then performs the standard
 //! `coalition.shutdown()` → cancel sdb token → drain sequence.
@@ -22,11 +22,12 @@
 //!
 //! # Prerequisites
 //! Docker must be running. The library spins up a SurrealDB container on start.
+//! Without Docker, run embedded instead: `SDB__ENDPOINT=mem:// cargo run --example messaging`.
 
 use anyhow::Context as _;
 use serde::{Deserialize, Serialize};
 use surrealdb_types::SurrealValue;
-use tokio::time::{Duration, sleep, timeout};
+use tokio::time::{Duration, sleep};
 use tokio_util::{sync::CancellationToken, task::TaskTracker};
 
 use surrealdb_live_message::logger;
@@ -39,11 +40,9 @@ const BOB: &str = "bob";
 const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
 const ROUNDTRIP_TIMEOUT: Duration = Duration::from_secs(15);
 
-/// Chat payload. `from` carries the sender's name so the recipient can reply
-/// without parsing the edge's `in` `RecordId`.
+/// Chat payload.
 #[derive(Debug, Clone, Serialize, Deserialize, SurrealValue)]
 pub struct Chat {
-    pub from: String,
     pub body: String,
 }
 
@@ -79,64 +78,55 @@ async fn main() -> anyhow::Result<()> {
         .await
         .context("failed to build coalition")?;
 
-    // Bob replies to whoever pinged him. Cloned out of the coalition so the
+    // Bob replies to whoever asked. Cloned out of the coalition so the
     // consumer task owns a handle for sending.
     let bob = coalition
         .agent(BOB)
         .await
         .context("bob missing from coalition")?;
 
-    // Consumer: observe the request, fire the reply, observe the reply, done.
+    // Consumer: answer every request that reaches bob. Replies bypass the
+    // inbox, so this loop only ever sees requests.
     let inbox = coalition.inbox();
     let consumer = tokio::spawn(async move {
         while let Ok(d) = inbox.recv().await {
             tracing::info!(
                 recipient = %d.recipient,
-                from = %d.message.payload.from,
                 body = %d.message.payload.body,
-                "delivered"
+                "request delivered"
             );
-            match d.recipient.as_str() {
-                BOB => {
-                    let reply = Chat {
-                        from: BOB.to_string(),
-                        body: format!("re: {}", d.message.payload.body),
-                    };
-                    if let Err(e) = bob.send(&d.message.payload.from, reply).await {
-                        tracing::error!("bob reply failed: {e}");
-                    }
-                }
-                // Reply landed back on the original sender — round-trip done.
-                _ => break,
+            let reply = Chat {
+                body: format!("re: {}", d.message.payload.body),
+            };
+            if let Err(e) = bob.reply(&d.message, reply).await {
+                tracing::error!("bob reply failed: {e}");
             }
         }
     });
 
-    // Kick off the round-trip.
     let alice = coalition
         .agent(ALICE)
         .await
         .context("alice missing from coalition")?;
-    alice
-        .send(
+    tracing::info!("alice pinging bob; awaiting reply …");
+    match alice
+        .request::<Chat, Chat>(
             BOB,
             Chat {
-                from: ALICE.to_string(),
                 body: "ping".to_string(),
             },
+            ROUNDTRIP_TIMEOUT,
         )
         .await
-        .context("alice → bob send")?;
-    tracing::info!("alice pinged bob; awaiting reply …");
-
-    match timeout(ROUNDTRIP_TIMEOUT, consumer).await {
-        Ok(_) => tracing::info!("round-trip complete"),
-        Err(_) => tracing::error!("round-trip did not complete within {ROUNDTRIP_TIMEOUT:?}"),
+    {
+        Ok(reply) => tracing::info!(body = %reply.payload.body, "round-trip complete"),
+        Err(e) => tracing::error!("round-trip failed: {e}"),
     }
 
-    // Standard shutdown order: coalition first (edges deleted while DB is up),
-    // then the sdb token, then drain.
+    // Standard shutdown order: coalition first, then the sdb token, then
+    // drain. The bus closes with the coalition, which ends the consumer.
     coalition.shutdown().await;
+    let _ = consumer.await;
     token.cancel();
     tracker.wait().await;
     tracing::info!("stopped cleanly");
@@ -24,7 +24,7 @@
 //! Press Ctrl-C or send `kill -TERM <pid>`.
 //!
 //! # Prerequisites
-//! Docker must be running.
+//! Docker must be running, or run embedded with `SDB__ENDPOINT=mem://`.
 
 use anyhow::Context as _;
 use serde::{Deserialize, Serialize};
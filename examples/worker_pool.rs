@@ -22,7 +22,7 @@
 //! ```
 //!
 //! # Prerequisites
-//! Docker must be running.
+//! Docker must be running, or run embedded with `SDB__ENDPOINT=mem://`.
 
 use anyhow::Context as _;
 use serde::{Deserialize, Serialize};
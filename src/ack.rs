@@ -0,0 +1,358 @@
+//! Explicit acknowledgement for the two-tier durable bus.
+//!
+//! Under [`AckMode::Auto`] (the default) an agent's persisted cursor advances as
+//! soon as a delivery is pushed onto the coalition bus, so a consumer that dies
+//! between `inbox().recv()` and finishing its work loses that message. Under
+//! [`AckMode::Manual`] every [`Delivery`](crate::subsystems::agents::Delivery)
+//! carries an [`Acker`]; the listen loop tracks in-flight deliveries in an
+//! [`AckTracker`] and only commits `cursor:<agent>` up to the lowest
+//! versionstamp that is still unacknowledged. Anything unacked is redelivered
+//! after its visibility timeout, on `nack`, or — because the cursor never moved
+//! past it — after a restart. With a `max_delivery_attempts` limit, a delivery
+//! that exhausts it is dead-lettered (see [`crate::dead_letter`]) and acked.
+//!
+//! Topic publications ([`crate::topic`]) go through the same tracker: each
+//! subscription's cursor only moves past its acknowledged publications.
+
+use std::collections::{BTreeMap, HashMap};
+
+use surrealdb_types::Value;
+use tokio::sync::mpsc;
+use tokio::time::{Duration, Instant};
+
+/// How a coalition's listen loops commit their durable-log cursors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AckMode {
+    /// Commit the cursor as soon as a delivery is pushed onto the bus.
+    #[default]
+    Auto,
+    /// Commit the cursor only past acknowledged deliveries. A delivery neither
+    /// acked nor nacked within `visibility_timeout` is redelivered.
+    Manual { visibility_timeout: Duration },
+}
+
+/// A consumer's verdict on one delivery, sent back to its agent's listen loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AckEvent {
+    Ack(u64),
+    Nack(u64),
+}
+
+/// Acknowledgement handle for one delivery. Cheap to clone; acking twice (or
+/// acking after a nack-triggered redelivery was acked) is harmless. Under
+/// [`AckMode::Auto`] both methods are no-ops.
+#[derive(Debug, Clone)]
+pub struct Acker(Option<(u64, mpsc::UnboundedSender<AckEvent>)>);
+
+impl Acker {
+    /// The no-op handle used under [`AckMode::Auto`].
+    pub(crate) fn auto() -> Self {
+        Self(None)
+    }
+
+    /// Mark the delivery as handled so the cursor may advance past it.
+    pub fn ack(&self) {
+        self.send(AckEvent::Ack);
+    }
+
+    /// Reject the delivery so it is redelivered immediately.
+    pub fn nack(&self) {
+        self.send(AckEvent::Nack);
+    }
+
+    fn send(&self, event: fn(u64) -> AckEvent) {
+        if let Some((tag, tx)) = &self.0 {
+            // The listen loop may already have shut down; the delivery is then
+            // replayed from the uncommitted cursor on restart anyway.
+            let _ = tx.send(event(*tag));
+        }
+    }
+}
+
+/// A tracked delivery that is due again, from [`AckTracker::take_expired`].
+#[derive(Debug)]
+pub(crate) struct Redelivery {
+    pub(crate) acker: Acker,
+    /// The topic of a publication; `None` for a direct message.
+    pub(crate) topic: Option<String>,
+    pub(crate) versionstamp: i64,
+    pub(crate) record: Value,
+    pub(crate) attempt: u32,
+}
+
+/// One delivery awaiting an ack.
+#[derive(Debug)]
+struct Pending {
+    topic: Option<String>,
+    versionstamp: i64,
+    record: Value,
+    deadline: Instant,
+    attempt: u32,
+}
+
+/// Per-agent in-flight bookkeeping for [`AckMode::Manual`], over every cursor
+/// the agent owns: `cursor:<agent>` for direct messages (`topic` `None`) and
+/// one per topic subscription.
+///
+/// Pure state over tags and versionstamps — no DB — so the commit and
+/// redelivery rules are unit-tested below without a running listen loop.
+#[derive(Debug)]
+pub(crate) struct AckTracker {
+    visibility_timeout: Duration,
+    max_attempts: Option<u32>,
+    tx: mpsc::UnboundedSender<AckEvent>,
+    next_tag: u64,
+    pending: HashMap<u64, Pending>,
+    /// Unacked delivery count per cursor and versionstamp; a cursor's first
+    /// key bounds its commit.
+    in_flight: HashMap<Option<String>, BTreeMap<i64, usize>>,
+    /// Last persisted point per cursor. A topic cursor is absent until its
+    /// first commit here.
+    committed: HashMap<Option<String>, i64>,
+}
+
+impl AckTracker {
+    /// `committed` is the message cursor already persisted for this agent;
+    /// `max_attempts` bounds deliveries per record (`None`: unbounded).
+    pub(crate) fn new(
+        visibility_timeout: Duration,
+        max_attempts: Option<u32>,
+        tx: mpsc::UnboundedSender<AckEvent>,
+        committed: i64,
+    ) -> Self {
+        Self {
+            visibility_timeout,
+            max_attempts,
+            tx,
+            next_tag: 0,
+            pending: HashMap::new(),
+            in_flight: HashMap::new(),
+            committed: HashMap::from([(None, committed)]),
+        }
+    }
+
+    /// Start tracking a first delivery of `record` (read at `versionstamp`
+    /// from `topic`'s cursor, or the message cursor for `None`) and hand back
+    /// its ack handle.
+    pub(crate) fn track(
+        &mut self,
+        topic: Option<&str>,
+        versionstamp: i64,
+        record: Value,
+        now: Instant,
+    ) -> Acker {
+        let tag = self.next_tag;
+        self.next_tag += 1;
+        let topic = topic.map(str::to_string);
+        *self
+            .in_flight
+            .entry(topic.clone())
+            .or_default()
+            .entry(versionstamp)
+            .or_default() += 1;
+        self.pending.insert(
+            tag,
+            Pending {
+                topic,
+                versionstamp,
+                record,
+                deadline: now + self.visibility_timeout,
+                attempt: 1,
+            },
+        );
+        self.acker(tag)
+    }
+
+    /// Apply a consumer verdict. Unknown tags (double acks) are ignored.
+    pub(crate) fn apply(&mut self, event: AckEvent, now: Instant) {
+        match event {
+            AckEvent::Ack(tag) => {
+                let Some(p) = self.pending.remove(&tag) else {
+                    return;
+                };
+                if let Some(in_flight) = self.in_flight.get_mut(&p.topic)
+                    && let Some(n) = in_flight.get_mut(&p.versionstamp)
+                {
+                    *n -= 1;
+                    if *n == 0 {
+                        in_flight.remove(&p.versionstamp);
+                    }
+                }
+            }
+            AckEvent::Nack(tag) => {
+                if let Some(p) = self.pending.get_mut(&tag) {
+                    p.deadline = now;
+                }
+            }
+        }
+    }
+
+    /// Deliveries whose deadline has passed. Each one's deadline is pushed out
+    /// by another visibility timeout and its attempt counter bumped, so it is
+    /// handed out once per expiry.
+    pub(crate) fn take_expired(&mut self, now: Instant) -> Vec<Redelivery> {
+        let mut expired: Vec<u64> = self
+            .pending
+            .iter()
+            .filter(|(_, p)| p.deadline <= now)
+            .map(|(tag, _)| *tag)
+            .collect();
+        // Tags are issued in delivery order; redeliver in the same order.
+        expired.sort_unstable();
+        expired
+            .into_iter()
+            .map(|tag| {
+                let p = self.pending.get_mut(&tag).expect("tag collected above");
+                p.deadline = now + self.visibility_timeout;
+                p.attempt += 1;
+                Redelivery {
+                    acker: Acker(Some((tag, self.tx.clone()))),
+                    topic: p.topic.clone(),
+                    versionstamp: p.versionstamp,
+                    record: p.record.clone(),
+                    attempt: p.attempt,
+                }
+            })
+            .collect()
+    }
+
+    /// Whether `redelivery` is past the attempt limit and should be
+    /// dead-lettered instead of sent again.
+    pub(crate) fn exhausted(&self, redelivery: &Redelivery) -> bool {
+        self.max_attempts
+            .is_some_and(|max| redelivery.attempt > max)
+    }
+
+    /// `topic`'s cursor to persist, if it moved: the lowest unacked
+    /// versionstamp, or `read_cursor` (the next unread versionstamp) once
+    /// nothing is in flight.
+    pub(crate) fn pending_commit(&self, topic: Option<&str>, read_cursor: i64) -> Option<i64> {
+        let key = topic.map(str::to_string);
+        let point = self
+            .in_flight
+            .get(&key)
+            .and_then(|in_flight| in_flight.keys().next().copied())
+            .unwrap_or(read_cursor);
+        match self.committed.get(&key) {
+            Some(&committed) => (point > committed).then_some(point),
+            None => Some(point),
+        }
+    }
+
+    /// Record that `topic`'s cursor was persisted at `point`.
+    pub(crate) fn set_committed(&mut self, topic: Option<&str>, point: i64) {
+        self.committed.insert(topic.map(str::to_string), point);
+    }
+
+    /// Stop tracking `topic` after an unsubscribe: its in-flight publications
+    /// can no longer hold back a cursor, and late acks for them are ignored.
+    pub(crate) fn forget(&mut self, topic: &str) {
+        let key = Some(topic.to_string());
+        self.pending.retain(|_, p| p.topic != key);
+        self.in_flight.remove(&key);
+        self.committed.remove(&key);
+    }
+
+    fn acker(&self, tag: u64) -> Acker {
+        Acker(Some((tag, self.tx.clone())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIS: Duration = Duration::from_secs(30);
+
+    fn tracker() -> (AckTracker, mpsc::UnboundedReceiver<AckEvent>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (AckTracker::new(VIS, Some(2), tx, 10), rx)
+    }
+
+    /// The commit point stops at the lowest unacked versionstamp and only
+    /// moves once the contiguous prefix is acknowledged.
+    #[test]
+    fn commits_only_contiguous_acks() {
+        let (mut t, mut rx) = tracker();
+        let now = Instant::now();
+        let a = t.track(None, 10, Value::None, now);
+        let b = t.track(None, 11, Value::None, now);
+
+        b.ack();
+        t.apply(rx.try_recv().expect("ack event"), now);
+        assert_eq!(t.pending_commit(None, 12), None, "10 is still unacked");
+
+        a.ack();
+        t.apply(rx.try_recv().expect("ack event"), now);
+        assert_eq!(t.pending_commit(None, 12), Some(12));
+        t.set_committed(None, 12);
+        assert_eq!(t.pending_commit(None, 12), None);
+    }
+
+    /// A nack makes the delivery immediately due; an unanswered one becomes due
+    /// after the visibility timeout. Both come back with a bumped attempt.
+    #[test]
+    fn redelivers_on_nack_and_timeout() {
+        let (mut t, mut rx) = tracker();
+        let now = Instant::now();
+        let a = t.track(None, 10, Value::None, now);
+        let _b = t.track(None, 11, Value::None, now);
+        assert!(t.take_expired(now).is_empty());
+
+        a.nack();
+        t.apply(rx.try_recv().expect("nack event"), now);
+        let due = t.take_expired(now);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].attempt, 2);
+        assert!(!t.exhausted(&due[0]), "2 of 2 attempts used");
+
+        let due = t.take_expired(now + VIS);
+        assert_eq!(due.len(), 2, "both deadlines have passed");
+        assert!(t.exhausted(&due[0]), "a's third attempt exceeds the limit");
+        assert!(!t.exhausted(&due[1]), "b is only on its second");
+        assert_eq!(t.pending_commit(None, 12), None, "nothing acked yet");
+    }
+
+    /// Acking an unknown or already-acked tag is ignored.
+    #[test]
+    fn double_ack_is_harmless() {
+        let (mut t, mut rx) = tracker();
+        let now = Instant::now();
+        let a = t.track(None, 10, Value::None, now);
+        a.ack();
+        a.ack();
+        t.apply(rx.try_recv().expect("ack event"), now);
+        t.apply(rx.try_recv().expect("ack event"), now);
+        assert_eq!(t.pending_commit(None, 11), Some(11));
+    }
+
+    /// Each topic subscription commits independently of the message cursor
+    /// and of other topics; an unsubscribed topic stops holding anything back.
+    #[test]
+    fn topic_cursors_commit_independently() {
+        let (mut t, mut rx) = tracker();
+        let now = Instant::now();
+        let message = t.track(None, 10, Value::None, now);
+        let alert = t.track(Some("alerts"), 3, Value::None, now);
+        let _news = t.track(Some("news"), 7, Value::None, now);
+
+        alert.ack();
+        t.apply(rx.try_recv().expect("ack event"), now);
+        assert_eq!(t.pending_commit(Some("alerts"), 4), Some(4));
+        t.set_committed(Some("alerts"), 4);
+        assert_eq!(t.pending_commit(Some("alerts"), 4), None);
+        assert_eq!(t.pending_commit(Some("news"), 8), Some(7), "7 is unacked");
+        assert_eq!(t.pending_commit(None, 11), None, "10 is unacked");
+
+        message.ack();
+        t.apply(rx.try_recv().expect("ack event"), now);
+        assert_eq!(t.pending_commit(None, 11), Some(11));
+
+        t.forget("news");
+        assert_eq!(t.pending_commit(Some("news"), 8), Some(8));
+        assert!(
+            t.take_expired(now + VIS).is_empty(),
+            "news is no longer tracked"
+        );
+    }
+}
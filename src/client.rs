@@ -0,0 +1,460 @@
+//! Explicit database handle.
+//!
+//! A [`Client`] bundles one `Surreal<Any>` connection with the [`Settings`] it
+//! belongs to. `Agent`, `Coalition` and the `dead_letter` helpers reach the
+//! database only through the client they were given, so one process can serve
+//! several databases or namespaces — one client per tenant — and tests can
+//! vary configuration without touching the environment.
+//!
+//! The process-wide connection behind
+//! [`SurrealDBWrapper::connection`](crate::subsystems::sdb::SurrealDBWrapper::connection)
+//! remains the default: [`Client::global`] wraps it together with `SETTINGS`,
+//! and it is what `Agent::new` and `Coalition::new` use.
+//!
+//! # Reconnection
+//!
+//! A `ws://`/`wss://` session can die (network loss, server restart) and the
+//! SDK does not rebuild it. [`Client::supervise`] probes the session and, when
+//! it stops answering, opens a fresh one with the same settings — connect,
+//! `use_ns`/`use_db`, signin, schema — and swaps it in for every clone of the
+//! client. Each step is published as a [`ConnectionEvent`]; the coalition's
+//! changefeed reader resubscribes on [`ConnectionEvent::Reconnected`] and
+//! re-drains from its agents' cursors. `sdb_task` runs the supervisor for the
+//! clients it creates.
+//!
+//! # Authentication
+//!
+//! `sdb.auth` picks how a client signs in: root (the default), a namespace or
+//! database user, a record access method, or a pre-issued JWT. Every
+//! reconnect signs in the same way. With `sdb.auth_refresh_secs` set, the
+//! supervisor also re-authenticates the live session on that interval, so a
+//! long-running coalition is not cut off when its session expires; a JWT
+//! issued elsewhere is rotated with [`Client::authenticate`].
+
+use std::fmt;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use surrealdb::Surreal;
+use surrealdb::engine::any;
+use surrealdb::opt::auth::{Database, Namespace, Record, Root};
+use tokio::sync::broadcast;
+use tokio::time::{
+    Duration, Instant, Interval, MissedTickBehavior, interval, interval_at, sleep, timeout,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::error::{Error, Result};
+use crate::settings::{Auth, Settings};
+use crate::subsystems::sdb::SurrealDBWrapper;
+
+/// Upper bound on the delay between connection attempts.
+const CONNECT_BACKOFF_MAX: Duration = Duration::from_secs(5);
+
+/// How often [`Client::supervise`] probes the session, and how long a probe
+/// may take before the session counts as dead.
+const PROBE_INTERVAL: Duration = Duration::from_secs(5);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Buffered [`ConnectionEvent`]s per subscriber before it starts lagging.
+const EVENT_CAPACITY: usize = 16;
+
+/// A connection state transition, published by [`Client::supervise`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// The session stopped answering health probes.
+    Disconnected { reason: String },
+    /// Opening a replacement session; `attempt` counts from 1.
+    Reconnecting { attempt: u32 },
+    /// A replacement session is up (namespace, database, signin and schema
+    /// applied) and in use by every clone of the client.
+    Reconnected,
+}
+
+/// A connection plus its settings. Cheap to clone; clones share the
+/// connection, including one swapped in after a reconnect.
+#[derive(Clone)]
+pub struct Client {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    db: RwLock<Surreal<any::Any>>,
+    settings: Settings,
+    /// Starts as `settings.sdb.auth`; [`Client::authenticate`] replaces it.
+    auth: RwLock<Auth>,
+    events: broadcast::Sender<ConnectionEvent>,
+}
+
+impl Client {
+    /// Connect to `settings.sdb.endpoint`, select its namespace and database,
+    /// sign in as `settings.sdb.auth` says (servers only — an embedded engine
+    /// has no users) and define the schema.
+    ///
+    /// An unreachable endpoint is retried up to `sdb.connect_attempts` times
+    /// with capped exponential backoff from `sdb.connect_backoff_ms`, then
+    /// returned as [`Error::Connect`]. [`Error::UseNsDb`], [`Error::Auth`] and
+    /// [`Error::Schema`] are configuration problems a retry cannot fix, so they
+    /// are returned at once.
+    pub async fn connect(settings: Settings) -> Result<Self> {
+        let attempts = settings.sdb.connect_attempts.max(1);
+        let mut backoff = Duration::from_millis(settings.sdb.connect_backoff_ms);
+        let mut attempt = 1;
+        let db = loop {
+            match open(&settings, &settings.sdb.auth).await {
+                Ok(db) => break db,
+                Err(Error::Connect(e)) if attempt < attempts => {
+                    tracing::warn!(
+                        "connecting to {} failed (attempt {attempt}/{attempts}), retrying in {backoff:?}: {e}",
+                        settings.sdb.endpoint
+                    );
+                    sleep(backoff).await;
+                    backoff = (backoff * 2).min(CONNECT_BACKOFF_MAX);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        Self::new(db, settings).await
+    }
+
+    /// Wrap a connection the caller has already set up (endpoint, namespace,
+    /// database, auth). Defines the schema in whatever namespace and database
+    /// `db` currently uses; `settings` supplies the rest (e.g. the retention
+    /// window) and is what [`Client::supervise`] reconnects with.
+    pub async fn new(db: Surreal<any::Any>, settings: Settings) -> Result<Self> {
+        apply_schema(&db, &settings, &settings.sdb.auth).await?;
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        Ok(Self {
+            inner: Arc::new(Inner {
+                db: RwLock::new(db),
+                auth: RwLock::new(settings.sdb.auth.clone()),
+                settings,
+                events,
+            }),
+        })
+    }
+
+    /// The process-wide default client: the shared
+    /// [`SurrealDBWrapper::connection`] with `SETTINGS`. Fails, and is retried
+    /// on the next call, while that connection cannot be established.
+    pub async fn global() -> Result<Self> {
+        SurrealDBWrapper::client().await.cloned()
+    }
+
+    /// The current session. Take it per operation rather than holding on to
+    /// it, so work after a reconnect uses the new session.
+    pub fn db(&self) -> Surreal<any::Any> {
+        self.inner
+            .db
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.inner.settings
+    }
+
+    /// Subscribe to this client's [`ConnectionEvent`]s.
+    pub fn events(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.inner.events.subscribe()
+    }
+
+    /// Switch to a new pre-issued JWT: authenticate the current session with
+    /// it now, and use it for every later reconnect and refresh. Call this
+    /// before the previous token expires.
+    pub async fn authenticate(&self, token: impl Into<String>) -> Result<()> {
+        let auth = Auth::Token {
+            token: token.into(),
+        };
+        signin(&self.db(), self.settings(), &auth).await?;
+        *self
+            .inner
+            .auth
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = auth;
+        Ok(())
+    }
+
+    /// Check that the server answers queries: a fresh session on
+    /// `sdb.endpoint`, signed in like this client, runs a trivial query
+    /// within `sdb.health.probe_timeout_ms`. Unlike the session probe of
+    /// [`supervise`](Self::supervise), this exercises the whole path a new
+    /// connection takes. An embedded engine runs the query on the current
+    /// session, since a fresh `mem://` session would be another database.
+    pub async fn check_ready(&self) -> Result<()> {
+        if self.settings().sdb.is_embedded() {
+            return run_probe_query(&self.db(), self.settings()).await;
+        }
+        check_ready(self.settings(), &self.auth()).await
+    }
+
+    /// `true` unless signed in with a record access method or a token: only
+    /// system users define the schema and sweep the tables.
+    pub(crate) fn is_system_user(&self) -> bool {
+        self.settings().sdb.is_embedded() || self.auth().is_system_user()
+    }
+
+    fn auth(&self) -> Auth {
+        self.inner
+            .auth
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Probe the session every few seconds until `token` cancels; when it
+    /// stops answering, reconnect with capped exponential backoff (from
+    /// `sdb.connect_backoff_ms`) until a new session is up or `token` cancels.
+    /// Publishes every transition as a [`ConnectionEvent`]. With
+    /// `sdb.auth_refresh_secs` set, also signs the session in again on that
+    /// interval.
+    ///
+    /// An embedded engine lives in this process and has no session to lose,
+    /// so for one this only waits for `token`. Run one supervisor per client.
+    pub async fn supervise(&self, token: CancellationToken) {
+        if self.settings().sdb.is_embedded() {
+            token.cancelled().await;
+            return;
+        }
+        let mut probe = interval(PROBE_INTERVAL);
+        probe.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut refresh = self.settings().sdb.auth_refresh_secs.map(|secs| {
+            let period = Duration::from_secs(secs.max(1));
+            let mut refresh = interval_at(Instant::now() + period, period);
+            refresh.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            refresh
+        });
+        loop {
+            tokio::select! {
+                biased;
+                _ = token.cancelled() => return,
+                _ = probe.tick() => {}
+                _ = next_refresh(&mut refresh) => {
+                    match signin(&self.db(), self.settings(), &self.auth()).await {
+                        Ok(()) => tracing::debug!("SurrealDB session re-authenticated"),
+                        Err(e) => tracing::warn!("re-authenticating the SurrealDB session failed: {e}"),
+                    }
+                    continue;
+                }
+            }
+            let Err(reason) = self.probe().await else {
+                continue;
+            };
+            tracing::warn!("SurrealDB session lost: {reason}");
+            self.emit(ConnectionEvent::Disconnected { reason });
+            if !self.reconnect(&token).await {
+                return;
+            }
+        }
+    }
+
+    /// One health probe against the current session.
+    async fn probe(&self) -> std::result::Result<(), String> {
+        match timeout(PROBE_TIMEOUT, self.db().health()).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err(format!("no health response within {PROBE_TIMEOUT:?}")),
+        }
+    }
+
+    /// Open sessions until one is fully set up, then swap it in. Returns
+    /// `false` if `token` cancelled first.
+    async fn reconnect(&self, token: &CancellationToken) -> bool {
+        let settings = self.settings();
+        let mut backoff = Duration::from_millis(settings.sdb.connect_backoff_ms);
+        let mut attempt = 1;
+        loop {
+            self.emit(ConnectionEvent::Reconnecting { attempt });
+            let reopened = async {
+                let db = open(settings, &self.auth()).await?;
+                apply_schema(&db, settings, &self.auth()).await?;
+                Ok::<_, Error>(db)
+            };
+            match reopened.await {
+                Ok(db) => {
+                    *self
+                        .inner
+                        .db
+                        .write()
+                        .unwrap_or_else(PoisonError::into_inner) = db;
+                    tracing::info!("SurrealDB session re-established");
+                    self.emit(ConnectionEvent::Reconnected);
+                    return true;
+                }
+                Err(e) => tracing::warn!("reconnect attempt {attempt} failed: {e}"),
+            }
+            tokio::select! {
+                _ = token.cancelled() => return false,
+                _ = sleep(backoff) => {}
+            }
+            backoff = (backoff * 2).min(CONNECT_BACKOFF_MAX);
+            attempt += 1;
+        }
+    }
+
+    /// Publish `event`; having no subscribers is fine.
+    fn emit(&self, event: ConnectionEvent) {
+        let _ = self.inner.events.send(event);
+    }
+}
+
+/// Resolves on the next auth refresh tick; never, without a refresh interval.
+async fn next_refresh(refresh: &mut Option<Interval>) {
+    match refresh {
+        Some(refresh) => {
+            refresh.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// One attempt at a ready session: connect, select namespace and database,
+/// and sign in with `auth` unless embedded.
+async fn open(settings: &Settings, auth: &Auth) -> Result<Surreal<any::Any>> {
+    let db = any::connect(&settings.sdb.endpoint)
+        .await
+        .map_err(Error::Connect)?;
+
+    db.use_ns(&settings.sdb.namespace)
+        .use_db(&settings.sdb.database)
+        .await
+        .map_err(Error::UseNsDb)?;
+
+    if !settings.sdb.is_embedded() {
+        signin(&db, settings, auth).await?;
+    }
+    Ok(db)
+}
+
+/// Open a session on `settings.sdb.endpoint` as `auth` and run a trivial
+/// query on it, within `sdb.health.probe_timeout_ms`; see
+/// [`Client::check_ready`].
+pub(crate) async fn check_ready(settings: &Settings, auth: &Auth) -> Result<()> {
+    within_probe_timeout(settings, async {
+        let db = open(settings, auth).await?;
+        probe_query(&db).await
+    })
+    .await
+}
+
+/// [`probe_query`] on `db`, within `sdb.health.probe_timeout_ms`.
+async fn run_probe_query(db: &Surreal<any::Any>, settings: &Settings) -> Result<()> {
+    within_probe_timeout(settings, probe_query(db)).await
+}
+
+async fn probe_query(db: &Surreal<any::Any>) -> Result<()> {
+    db.query("RETURN true")
+        .await
+        .map_err(Error::Probe)?
+        .check()
+        .map_err(Error::Probe)?;
+    Ok(())
+}
+
+async fn within_probe_timeout(
+    settings: &Settings,
+    probe: impl Future<Output = Result<()>>,
+) -> Result<()> {
+    let limit = Duration::from_millis(settings.sdb.health.probe_timeout_ms);
+    timeout(limit, probe)
+        .await
+        .map_err(|_| Error::ProbeTimeout { timeout: limit })?
+}
+
+/// Define the schema when signed in as a system user. A record or token
+/// session has no right to, and uses the schema a system user defined.
+async fn apply_schema(db: &Surreal<any::Any>, settings: &Settings, auth: &Auth) -> Result<()> {
+    if settings.sdb.is_embedded() || auth.is_system_user() {
+        SurrealDBWrapper::define_schema(db, &settings.sdb).await?;
+    }
+    Ok(())
+}
+
+/// Authenticate `db` as `auth` describes. Namespace, database and record
+/// signins are scoped to `sdb.namespace`/`sdb.database`.
+async fn signin(db: &Surreal<any::Any>, settings: &Settings, auth: &Auth) -> Result<()> {
+    let sdb = &settings.sdb;
+    match auth {
+        Auth::Root => db
+            .signin(Root {
+                username: sdb.username.clone(),
+                password: sdb.password.clone(),
+            })
+            .await
+            .map(drop),
+        Auth::Namespace { username, password } => db
+            .signin(Namespace {
+                namespace: sdb.namespace.clone(),
+                username: username.clone(),
+                password: password.clone(),
+            })
+            .await
+            .map(drop),
+        Auth::Database { username, password } => db
+            .signin(Database {
+                namespace: sdb.namespace.clone(),
+                database: sdb.database.clone(),
+                username: username.clone(),
+                password: password.clone(),
+            })
+            .await
+            .map(drop),
+        Auth::Record {
+            access,
+            params,
+            signup,
+        } => {
+            let record = || Record {
+                namespace: sdb.namespace.clone(),
+                database: sdb.database.clone(),
+                access: access.clone(),
+                params: params.clone(),
+            };
+            match db.signin(record()).await {
+                Ok(_) => Ok(()),
+                Err(e) if *signup && is_no_record(&e.to_string()) => {
+                    tracing::info!("no record to sign in via '{access}', signing up");
+                    db.signup(record()).await.map(drop)
+                }
+                Err(e) => Err(e),
+            }
+        }
+        Auth::Token { token } => db.authenticate(token.clone()).await.map(drop),
+    }
+    .map_err(Error::Auth)
+}
+
+/// Whether a record signin failed because its `SIGNIN` clause returned no
+/// record — the only failure a signup can fix. A network error or a rejected
+/// clause is returned as [`Error::Auth`] instead. (A `SIGNIN` that checks the
+/// password in its `WHERE` cannot tell a wrong password from a missing
+/// record; the signup that follows then fails on the existing record.)
+fn is_no_record(error: &str) -> bool {
+    error.contains("No record was returned")
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sdb = &self.settings().sdb;
+        f.debug_struct("Client")
+            .field("endpoint", &sdb.endpoint)
+            .field("namespace", &sdb.namespace)
+            .field("database", &sdb.database)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_up_only_without_a_record() {
+        assert!(is_no_record("No record was returned"));
+        assert!(!is_no_record("There was a problem with authentication"));
+        assert!(!is_no_record(
+            "There was an error processing a remote WS request: connection reset"
+        ));
+    }
+}
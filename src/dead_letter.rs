@@ -0,0 +1,136 @@
+//! Dead letters: `message` and `publication` records an agent could not
+//! deliver.
+//!
+//! A change whose payload fails `Message::<T>::from_value` is written to the
+//! `dead_letter` table — raw record, agent, reason and versionstamp — and the
+//! agent's cursor moves past it, so one bad record never blocks an agent and
+//! is never silently lost. Under [`AckMode::Manual`](crate::ack::AckMode) a
+//! delivery still unacknowledged after
+//! `CoalitionOptions::max_delivery_attempts` is dead-lettered the same way.
+//!
+//! [`list`] and [`get`] read the table back through a [`Client`], e.g. to
+//! diagnose schema drift between producers and consumers; [`redrive`]
+//! re-sends a dead letter to its original recipient once that is fixed.
+
+use surrealdb::Surreal;
+use surrealdb::engine::any;
+use surrealdb_types::{Datetime, RecordId, SurrealValue, ToSql, Value};
+
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::subsystems::agents::AGENT_TABLE;
+
+pub const DEAD_LETTER_TABLE: &str = "dead_letter";
+
+/// One undeliverable change, as stored in the `dead_letter` table.
+#[derive(Debug, Clone, SurrealValue)]
+pub struct DeadLetter {
+    pub id: RecordId,
+    /// The agent the record was addressed to.
+    pub agent: String,
+    /// The `message` or `publication` edge exactly as read from the
+    /// changefeed.
+    pub record: Value,
+    /// The decode error, or why redelivery gave up.
+    pub reason: String,
+    /// Changefeed versionstamp the record was read at.
+    pub versionstamp: i64,
+    pub created: Datetime,
+}
+
+/// The fields written on insert; `id` and `created` are assigned by the DB.
+#[derive(Debug, SurrealValue)]
+struct DeadLetterRow {
+    agent: String,
+    record: Value,
+    reason: String,
+    versionstamp: i64,
+}
+
+/// Write one dead letter for `agent`.
+pub(crate) async fn record(
+    db: &Surreal<any::Any>,
+    agent: &str,
+    record: Value,
+    reason: String,
+    versionstamp: i64,
+) -> Result<()> {
+    let _: Option<Value> = db
+        .create(DEAD_LETTER_TABLE)
+        .content(DeadLetterRow {
+            agent: agent.to_string(),
+            record,
+            reason,
+            versionstamp,
+        })
+        .await
+        .map_err(|source| Error::DeadLetterWrite {
+            agent: agent.to_string(),
+            source,
+        })?;
+    Ok(())
+}
+
+/// Dead letters oldest first, optionally only those for `agent`.
+pub async fn list(client: &Client, agent: Option<&str>) -> Result<Vec<DeadLetter>> {
+    let query = match agent {
+        Some(_) => "SELECT * FROM dead_letter WHERE agent = $agent ORDER BY created",
+        None => "SELECT * FROM dead_letter ORDER BY created",
+    };
+    client
+        .db()
+        .query(query)
+        .bind(("agent", agent.map(str::to_string)))
+        .await
+        .map_err(Error::DeadLetterQuery)?
+        .take(0)
+        .map_err(Error::DeadLetterQuery)
+}
+
+/// One dead letter by id, if it still exists.
+pub async fn get(client: &Client, id: &RecordId) -> Result<Option<DeadLetter>> {
+    client
+        .db()
+        .select(id.clone())
+        .await
+        .map_err(Error::DeadLetterQuery)
+}
+
+/// Re-send dead letter `id` to its agent as a fresh `message` edge — same
+/// sender, payload and correlation fields — and delete it, in one
+/// transaction. The new edge goes through the normal durable path, so the
+/// recipient sees it whether or not it is running right now. A dead-lettered
+/// publication is re-sent point-to-point to that one subscriber, not
+/// re-published to the topic. Returns [`Error::UnknownDeadLetter`] if `id`
+/// does not exist.
+pub async fn redrive(client: &Client, id: &RecordId) -> Result<()> {
+    let Some(letter) = get(client, id).await? else {
+        return Err(Error::UnknownDeadLetter { id: id.to_sql() });
+    };
+    let field = |name: &str| match &letter.record {
+        Value::Object(o) => o.get(name).cloned().unwrap_or(Value::None),
+        _ => Value::None,
+    };
+
+    let query = "BEGIN TRANSACTION; \
+        RELATE $from->message->$to CONTENT { \
+            created: time::now(), payload: $payload, \
+            correlation_id: $correlation_id, reply_to: $reply_to }; \
+        DELETE $id; \
+        COMMIT TRANSACTION;";
+    client
+        .db()
+        .query(query)
+        .bind(("from", field("in")))
+        .bind(("to", RecordId::new(AGENT_TABLE, letter.agent.as_str())))
+        .bind(("payload", field("payload")))
+        .bind(("correlation_id", field("correlation_id")))
+        .bind(("reply_to", field("reply_to")))
+        .bind(("id", id.clone()))
+        .await
+        .map_err(Error::DeadLetterQuery)?
+        .check()
+        .map_err(Error::DeadLetterQuery)?;
+    tracing::info!(agent = %letter.agent, "dead letter re-driven");
+    Ok(())
+}
@@ -8,9 +8,14 @@
 
 use std::time::Duration;
 
+use crate::sdb_server::StartupDiagnostics;
+
 /// All fallible operations in the library funnel through this enum.
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
+    #[error("invalid configuration: {reason}")]
+    Config { reason: String },
+
     #[error("failed to connect to SurrealDB")]
     Connect(#[source] surrealdb::Error),
 
@@ -23,6 +28,17 @@ pub enum Error {
     #[error("failed to apply SurrealDB schema")]
     Schema(#[source] surrealdb::Error),
 
+    #[error("SurrealDB readiness probe query failed")]
+    Probe(#[source] surrealdb::Error),
+
+    #[error("SurrealDB readiness probe got no answer within {timeout:?}")]
+    ProbeTimeout { timeout: Duration },
+
+    #[error(
+        "database schema is at version {found}, newer than this library supports ({supported})"
+    )]
+    SchemaVersion { found: u32, supported: u32 },
+
     #[error("failed to create agent '{agent}'")]
     AgentCreate {
         agent: String,
@@ -49,16 +65,42 @@ pub enum Error {
     #[error("recipient agent '{to}' does not exist")]
     UnknownRecipient { to: String },
 
-    #[error("LIVE SELECT registration failed for agent '{agent}'")]
-    LiveQuery {
-        agent: String,
+    #[error(
+        "invalid topic name '{name}': must be a non-empty record-id key \
+         (ASCII alphanumeric or underscore)"
+    )]
+    InvalidTopicName { name: String },
+
+    #[error("failed to publish to topic '{topic}'")]
+    Publish {
+        topic: String,
         #[source]
         source: surrealdb::Error,
     },
 
-    #[error("failed to open message stream for agent '{agent}'")]
-    Stream {
+    #[error("failed to update agent '{agent}' subscription to topic '{topic}'")]
+    Subscribe {
         agent: String,
+        topic: String,
+        #[source]
+        source: surrealdb::Error,
+    },
+
+    #[error("no reply from '{to}' within {timeout:?}")]
+    RequestTimeout { to: String, timeout: Duration },
+
+    #[error("reply from '{from}' could not be decoded: {reason}")]
+    ReplyDecode { from: String, reason: String },
+
+    #[error("cannot reply to a message that carries no correlation id or sender")]
+    NotARequest,
+
+    #[error("failed to register the coalition's LIVE wake-up")]
+    WakeUp(#[source] surrealdb::Error),
+
+    #[error("durable-log scan (SHOW CHANGES) failed for agents {agents}")]
+    Changefeed {
+        agents: String,
         #[source]
         source: surrealdb::Error,
     },
@@ -84,6 +126,31 @@ pub enum Error {
         source: surrealdb::Error,
     },
 
+    #[error("failed to record a dead letter for agent '{agent}'")]
+    DeadLetterWrite {
+        agent: String,
+        #[source]
+        source: surrealdb::Error,
+    },
+
+    #[error("dead-letter query failed")]
+    DeadLetterQuery(#[source] surrealdb::Error),
+
+    #[error("dead letter '{id}' does not exist")]
+    UnknownDeadLetter { id: String },
+
+    #[error("agent '{agent}' is already a member of this coalition")]
+    DuplicateAgent { agent: String },
+
+    #[error("agent '{agent}' is not a member of this coalition")]
+    NotAMember { agent: String },
+
+    #[error("agent '{agent}' already has a handler registered")]
+    HandlerExists { agent: String },
+
+    #[error("coalition has been shut down")]
+    CoalitionClosed,
+
     #[error("agent '{agent}' listen_loop did not signal ready within {timeout:?}")]
     ReadyTimeout { agent: String, timeout: Duration },
 
@@ -93,11 +160,23 @@ pub enum Error {
     #[error("database ready channel closed")]
     ReadyChannelClosed,
 
-    #[error("SurrealDB health check failed after all attempts")]
-    HealthCheck,
+    /// The typed error [`sdb_task`](crate::subsystems::sdb::sdb_task) failed
+    /// with (`Connect`, `Auth`, `Schema`, …), shared with every
+    /// `wait_until_ready()` caller; match on `&*error` to inspect it.
+    #[error(transparent)]
+    StartupFailed(std::sync::Arc<Error>),
+
+    #[error("SurrealDB health check failed after all attempts{diagnostics}")]
+    HealthCheck { diagnostics: StartupDiagnostics },
+
+    #[error("timed out waiting for SurrealDB to start{diagnostics}")]
+    StartupTimeout { diagnostics: StartupDiagnostics },
+
+    #[error("failed to pull image '{image}': {reason}")]
+    ImagePull { image: String, reason: String },
 
-    #[error("timed out waiting for SurrealDB to start")]
-    StartupTimeout,
+    #[error("Docker published no host port for container '{container}'")]
+    HostPort { container: String },
 
     #[error(transparent)]
     Docker(#[from] bollard::errors::Error),
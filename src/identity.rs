@@ -0,0 +1,77 @@
+//! Per-agent database identities.
+//!
+//! With `sdb.secured` the schema defines an `agent` record access method and
+//! row-level permissions on the bus tables, so an agent process signed in as
+//! its own `agent` record can only send as itself, read messages it sent or
+//! received, and move its own cursor — the database enforces isolation
+//! between agents from different trust domains, not just `Agent::new`'s name
+//! check.
+//!
+//! An operator (a system user) [`provision`]s each agent with a secret; the
+//! agent process then connects with [`auth`] as its `sdb.auth` and sends and
+//! publishes through an [`Agent`](crate::subsystems::agents::Agent) handle on
+//! that client. Record sessions never define the schema or run the retention
+//! sweep.
+//!
+//! **Not yet covered: receiving.** A coalition reads the changefeed (`SHOW
+//! CHANGES`), which needs a system user and is not filtered by the row-level
+//! permissions, so delivery still runs on an operator's coalition, and that
+//! system-user connection writes every agent's `cursor` row — the "own cursor
+//! only" rule binds record sessions that touch `cursor` directly, not the bus.
+//! A receive path for record sessions (a `LIVE SELECT`/`SELECT` on `message`
+//! scoped by `$auth`, resuming from `cursor:<agent>`) is a follow-up request.
+
+use std::collections::BTreeMap;
+
+use surrealdb_types::RecordId;
+
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::settings::Auth;
+use crate::subsystems::agents::AGENT_TABLE;
+
+/// Name of the record access method agents sign in through.
+pub const ACCESS: &str = "agent";
+
+/// Create agent `name`, or update its secret if it exists. The secret is
+/// stored as an argon2 hash. Run on a system user's client; record sessions
+/// cannot write `agent` rows.
+pub async fn provision(client: &Client, name: &str, secret: &str) -> Result<()> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(Error::InvalidAgentName {
+            name: name.to_string(),
+        });
+    }
+    let create_err = |source| Error::AgentCreate {
+        agent: name.to_string(),
+        source,
+    };
+    client
+        .db()
+        .query(
+            "UPSERT $agent SET name = $name, \
+             secret = crypto::argon2::generate($secret), \
+             created = created ?? time::now()",
+        )
+        .bind(("agent", RecordId::new(AGENT_TABLE, name)))
+        .bind(("name", name.to_string()))
+        .bind(("secret", secret.to_string()))
+        .await
+        .map_err(create_err)?
+        .check()
+        .map_err(create_err)?;
+    Ok(())
+}
+
+/// `sdb.auth` for signing in as agent `name` with the secret it was
+/// provisioned with.
+pub fn auth(name: &str, secret: &str) -> Auth {
+    Auth::Record {
+        access: ACCESS.to_string(),
+        params: BTreeMap::from([
+            ("name".to_string(), name.to_string()),
+            ("secret".to_string(), secret.to_string()),
+        ]),
+        signup: false,
+    }
+}
@@ -1,9 +1,17 @@
 pub mod subsystems {
     pub mod agents;
+    pub mod changefeed;
+    pub mod dispatch;
     pub mod sdb;
 }
+pub mod ack;
+pub mod client;
+pub mod dead_letter;
 pub mod error;
+pub mod identity;
 pub mod logger;
 pub mod message;
+pub mod migrate;
 pub mod sdb_server;
 pub mod settings;
+pub mod topic;
@@ -1,17 +1,25 @@
-use crate::settings::SETTINGS;
+use crate::settings::Settings;
 use std::str::FromStr;
 use tracing::Level;
 
+/// Install the global `tracing` subscriber at `logger.level`.
+///
+/// Never panics on a bad configuration: it logs at INFO instead, so the
+/// [`Error::Config`](crate::error::Error::Config) that `Settings::global()`
+/// returns can still be reported (e.g. by `sdb_task`).
 pub fn setup() {
-    let level = match Level::from_str(SETTINGS.logger.level.as_str()) {
-        Ok(level) => level,
-        Err(_) => {
-            eprintln!(
-                "Invalid log level: {}, defaulting to INFO",
-                SETTINGS.logger.level
-            );
-            Level::INFO
-        }
+    let level = match Settings::global() {
+        Ok(settings) => match Level::from_str(settings.logger.level.as_str()) {
+            Ok(level) => level,
+            Err(_) => {
+                eprintln!(
+                    "Invalid log level: {}, defaulting to INFO",
+                    settings.logger.level
+                );
+                Level::INFO
+            }
+        },
+        Err(_) => Level::INFO,
     };
 
     tracing_subscriber::fmt().with_max_level(level).init();
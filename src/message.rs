@@ -21,8 +21,8 @@ pub const MESSAGE_TABLE: &str = "message";
 ///    Read the edge pointers with an explicit projection —
 ///    `SELECT *, in, out FROM message WHERE ...`. The two-tier durable bus
 ///    sidesteps this on the delivery path: `SHOW CHANGES` changeset records
-///    carry `id`/`in`/`out` natively, so the wake-up subscription in
-///    `agents::Agent::listen_loop` is only `LIVE SELECT id`.
+///    carry `id`/`in`/`out` natively, so the coalition's wake-up
+///    subscription (`changefeed::subscribe`) is only `LIVE SELECT id`.
 #[derive(Debug, Serialize, Deserialize, SurrealValue)]
 pub struct Message<T: SurrealValue> {
     /// The edge record's own id. Populated on **delivery** (the durable-log
@@ -36,4 +36,11 @@ pub struct Message<T: SurrealValue> {
     pub out: Option<RecordId>,
     pub payload: T,
     pub created: Option<Datetime>,
+    /// Set on a request sent with `Agent::request`: the id the reply echoes
+    /// back in `reply_to`. `None` on plain sends.
+    pub correlation_id: Option<String>,
+    /// Set on a reply sent with `Agent::reply`: the `correlation_id` of the
+    /// request it answers. The requester's listen loop routes such an edge
+    /// straight to the waiting `request` future instead of the inbox.
+    pub reply_to: Option<String>,
 }
@@ -0,0 +1,501 @@
+//! Versioned schema migrations.
+//!
+//! The schema is built by an ordered list of [`MIGRATIONS`]. The highest
+//! applied version lives in `schema_version:current`; each pending migration
+//! runs in one transaction together with its version bump, so a crash never
+//! leaves a half-applied step behind. A database created before versioning
+//! reads as version 0 — every step uses `IF NOT EXISTS`, so replaying the
+//! baseline over existing tables is harmless.
+//!
+//! `CHANGEFEED` windows are not versioned: they follow
+//! `sdb.message_retention_secs` and are reconciled on every run, with
+//! `ALTER TABLE ... CHANGEFEED`. Tables are never redefined or removed, so the
+//! recorded history survives. A feed that is missing (a `message` table
+//! older than the durable bus) or shorter than the setting is extended. A
+//! longer one is kept unless `sdb.allow_changefeed_shrink` is set, because
+//! shrinking drops changes an offline agent may not have consumed yet.
+//!
+//! A migration marked `secured` (the agent access method and row-level
+//! permissions, see [`crate::identity`]) only runs its statements with
+//! `sdb.secured`; on an unsecured database it just records its version.
+//! `schema_version:current` records whether the secured steps ran, so turning
+//! `sdb.secured` on later replays them. Turning it off leaves the permissions
+//! in place.
+//!
+//! [`plan`] reports what [`apply`] would do without changing anything. With
+//! `sdb.auto_migrate` (the default) connecting a [`Client`] applies the plan;
+//! without it, connecting only logs pending steps and an operator runs
+//! [`apply`].
+
+use std::fmt;
+
+use surrealdb::Surreal;
+use surrealdb::engine::any;
+use surrealdb_types::Value;
+
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::message::MESSAGE_TABLE;
+use crate::settings::Sdb;
+use crate::topic::PUBLICATION_TABLE;
+
+pub const SCHEMA_VERSION_TABLE: &str = "schema_version";
+
+/// One schema step. `statements` receives the retention window in seconds
+/// for the `CHANGEFEED` of tables it creates. A `secured` step only runs its
+/// statements with `sdb.secured`.
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    pub secured: bool,
+    statements: fn(u64) -> String,
+}
+
+/// Every migration, in the order they apply. Append only: a released step is
+/// never edited, since databases already past it will not run it again.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "baseline: agent, message, cursor, dead_letter and topic tables",
+        secured: false,
+        statements: baseline,
+    },
+    Migration {
+        version: 2,
+        description: "index message and publication by created for the retention sweep",
+        secured: false,
+        statements: created_indexes,
+    },
+    Migration {
+        version: 3,
+        description: "secured: agent record access and row-level permissions",
+        secured: true,
+        statements: secured,
+    },
+];
+
+/// The version a fully migrated database is at.
+pub fn latest() -> u32 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// The last `secured` migration; applying it marks the database secured.
+fn latest_secured() -> Option<u32> {
+    MIGRATIONS
+        .iter()
+        .rev()
+        .find(|m| m.secured)
+        .map(|m| m.version)
+}
+
+/// The migrations to run on a database at `from_version`, in order: every
+/// later one, and the secured ones it skipped once `secured` (`sdb.secured`)
+/// is on. `db_secured` says whether its secured steps already ran.
+fn pending(
+    from_version: u32,
+    db_secured: bool,
+    secured: bool,
+) -> impl Iterator<Item = &'static Migration> {
+    MIGRATIONS
+        .iter()
+        .filter(move |m| m.version > from_version || (m.secured && secured && !db_secured))
+}
+
+fn baseline(retention: u64) -> String {
+    format!(
+        "
+        DEFINE TABLE IF NOT EXISTS agent SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS name ON agent TYPE string;
+        DEFINE FIELD IF NOT EXISTS created ON agent TYPE datetime;
+
+        DEFINE TABLE IF NOT EXISTS message TYPE RELATION IN agent OUT agent SCHEMALESS CHANGEFEED {retention}s;
+        DEFINE FIELD IF NOT EXISTS created ON message TYPE datetime;
+
+        DEFINE TABLE IF NOT EXISTS cursor SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS versionstamp ON cursor TYPE int;
+
+        DEFINE TABLE IF NOT EXISTS dead_letter SCHEMALESS;
+        DEFINE FIELD IF NOT EXISTS agent ON dead_letter TYPE string;
+        DEFINE FIELD IF NOT EXISTS reason ON dead_letter TYPE string;
+        DEFINE FIELD IF NOT EXISTS versionstamp ON dead_letter TYPE int;
+        DEFINE FIELD IF NOT EXISTS created ON dead_letter TYPE datetime DEFAULT time::now();
+        DEFINE INDEX IF NOT EXISTS dead_letter_agent ON dead_letter FIELDS agent;
+
+        DEFINE TABLE IF NOT EXISTS topic SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS name ON topic TYPE string;
+
+        DEFINE TABLE IF NOT EXISTS publication TYPE RELATION IN agent OUT topic SCHEMALESS CHANGEFEED {retention}s;
+        DEFINE FIELD IF NOT EXISTS created ON publication TYPE datetime;
+
+        DEFINE TABLE IF NOT EXISTS subscribes TYPE RELATION IN agent OUT topic SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS versionstamp ON subscribes TYPE int;
+        DEFINE INDEX IF NOT EXISTS subscribes_unique ON subscribes FIELDS in, out UNIQUE;
+        "
+    )
+}
+
+fn created_indexes(_retention: u64) -> String {
+    "
+    DEFINE INDEX IF NOT EXISTS message_created ON message FIELDS created;
+    DEFINE INDEX IF NOT EXISTS publication_created ON publication FIELDS created;
+    "
+    .to_string()
+}
+
+/// Row-level permissions for agents signed in through the `agent` record
+/// access method (see [`crate::identity`]), where `$auth.id` is the agent's
+/// own record. They bind record sessions only; system users (root, namespace,
+/// database) bypass them, so a coalition run by an operator is unaffected.
+///
+/// - `message`: create only edges sent as yourself (`in`), read only edges
+///   you sent or received; edges are immutable.
+/// - `cursor`: read and write only `cursor:<your name>`.
+/// - `publication` / `subscribes`: publish as yourself; read publications of
+///   topics you subscribe to; manage only your own subscriptions.
+/// - `dead_letter`: read and record only your own.
+/// - `agent`: readable (recipients are checked on send) but provisioned only
+///   by a system user; the hashed `secret` is never readable.
+///
+/// Tables are changed with `ALTER TABLE`, never redefined, so the `message`
+/// and `publication` changefeeds survive enabling it. Every statement is
+/// idempotent, so it is safe to replay on a database secured later.
+fn secured(_retention: u64) -> String {
+    "
+    DEFINE FIELD IF NOT EXISTS secret ON agent TYPE option<string> PERMISSIONS FOR select NONE;
+    DEFINE ACCESS IF NOT EXISTS agent ON DATABASE TYPE RECORD
+        SIGNIN (SELECT * FROM agent WHERE name = $name AND crypto::argon2::compare(secret, $secret));
+
+    ALTER TABLE agent PERMISSIONS
+        FOR select FULL
+        FOR create, update, delete NONE;
+    ALTER TABLE message PERMISSIONS
+        FOR select WHERE in = $auth.id OR out = $auth.id
+        FOR create WHERE in = $auth.id
+        FOR update, delete NONE;
+    ALTER TABLE cursor PERMISSIONS
+        FOR select, create, update WHERE record::id(id) = record::id($auth.id)
+        FOR delete NONE;
+    ALTER TABLE topic PERMISSIONS
+        FOR select, create, update FULL
+        FOR delete NONE;
+    ALTER TABLE publication PERMISSIONS
+        FOR select WHERE in = $auth.id OR out INSIDE (SELECT VALUE out FROM subscribes WHERE in = $auth.id)
+        FOR create WHERE in = $auth.id
+        FOR update, delete NONE;
+    ALTER TABLE subscribes PERMISSIONS
+        FOR select, create, update, delete WHERE in = $auth.id;
+    ALTER TABLE dead_letter PERMISSIONS
+        FOR select, create WHERE agent = record::id($auth.id)
+        FOR update, delete NONE;
+    "
+    .to_string()
+}
+
+/// Tables whose `CHANGEFEED` window tracks `sdb.message_retention_secs`.
+const CHANGEFEED_TABLES: [&str; 2] = [MESSAGE_TABLE, PUBLICATION_TABLE];
+
+/// One thing [`apply`] does, or deliberately does not do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// Run migration `version`.
+    Migrate {
+        version: u32,
+        description: &'static str,
+    },
+    /// Set `table`'s `CHANGEFEED` window to `to` seconds (`from` is `None`
+    /// when the table has no feed yet).
+    Changefeed {
+        table: &'static str,
+        from: Option<u64>,
+        to: u64,
+    },
+    /// `table`'s window stays at `current` seconds rather than shrinking to
+    /// the configured `requested`; see `sdb.allow_changefeed_shrink`.
+    KeepChangefeed {
+        table: &'static str,
+        current: u64,
+        requested: u64,
+    },
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Migrate {
+                version,
+                description,
+            } => write!(f, "migration {version}: {description}"),
+            Self::Changefeed {
+                table,
+                from: None,
+                to,
+            } => write!(f, "add CHANGEFEED {to}s to {table}"),
+            Self::Changefeed {
+                table,
+                from: Some(from),
+                to,
+            } => write!(f, "change {table} CHANGEFEED from {from}s to {to}s"),
+            Self::KeepChangefeed {
+                table,
+                current,
+                requested,
+            } => write!(
+                f,
+                "keep {table} CHANGEFEED at {current}s (configured {requested}s would drop history)"
+            ),
+        }
+    }
+}
+
+/// What [`apply`] would do to bring a database from `from_version` to
+/// `to_version`, in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plan {
+    pub from_version: u32,
+    pub to_version: u32,
+    pub steps: Vec<Step>,
+}
+
+impl Plan {
+    /// `true` when nothing would change. A kept changefeed window is not a
+    /// change.
+    pub fn is_current(&self) -> bool {
+        self.steps
+            .iter()
+            .all(|s| matches!(s, Step::KeepChangefeed { .. }))
+    }
+}
+
+/// Dry run: the steps [`apply`] would take on `client`'s database.
+pub async fn plan(client: &Client) -> Result<Plan> {
+    plan_for(&client.db(), &client.settings().sdb).await
+}
+
+/// Apply every pending step to `client`'s database and return what was done.
+/// Needs a system user (root, namespace or database).
+pub async fn apply(client: &Client) -> Result<Plan> {
+    apply_to(&client.db(), &client.settings().sdb).await
+}
+
+pub(crate) async fn plan_for(db: &Surreal<any::Any>, sdb: &Sdb) -> Result<Plan> {
+    let (from_version, db_secured) = current_version(db).await?;
+    let to_version = latest();
+    if from_version > to_version {
+        return Err(Error::SchemaVersion {
+            found: from_version,
+            supported: to_version,
+        });
+    }
+    let mut steps: Vec<Step> = pending(from_version, db_secured, sdb.secured)
+        .map(|m| Step::Migrate {
+            version: m.version,
+            description: m.description,
+        })
+        .collect();
+
+    // Only existing tables: one a pending migration creates gets the
+    // configured window there.
+    let requested = sdb.message_retention_secs;
+    for (table, current) in changefeed_windows(db).await? {
+        match current {
+            Some(current) if current == requested => {}
+            Some(current) if current > requested && !sdb.allow_changefeed_shrink => {
+                steps.push(Step::KeepChangefeed {
+                    table,
+                    current,
+                    requested,
+                })
+            }
+            from => steps.push(Step::Changefeed {
+                table,
+                from,
+                to: requested,
+            }),
+        }
+    }
+    Ok(Plan {
+        from_version,
+        to_version,
+        steps,
+    })
+}
+
+pub(crate) async fn apply_to(db: &Surreal<any::Any>, sdb: &Sdb) -> Result<Plan> {
+    let plan = plan_for(db, sdb).await?;
+    for step in &plan.steps {
+        let statements = match step {
+            Step::Migrate { version, .. } => {
+                let migration = MIGRATIONS
+                    .iter()
+                    .find(|m| m.version == *version)
+                    .expect("planned migrations come from MIGRATIONS");
+                // A replayed secured step must not move the version back.
+                let to = (*version).max(plan.from_version);
+                let statements = if migration.secured && !sdb.secured {
+                    String::new()
+                } else {
+                    (migration.statements)(sdb.message_retention_secs)
+                };
+                let secured = if sdb.secured && latest_secured() == Some(*version) {
+                    ", secured = true"
+                } else {
+                    ""
+                };
+                format!(
+                    "BEGIN TRANSACTION;
+                    {statements}
+                    UPSERT {SCHEMA_VERSION_TABLE}:current SET version = {to}{secured}, applied = time::now();
+                    COMMIT TRANSACTION;"
+                )
+            }
+            Step::Changefeed { table, to, .. } => {
+                format!("ALTER TABLE {table} CHANGEFEED {to}s;")
+            }
+            Step::KeepChangefeed { .. } => {
+                tracing::warn!("schema: {step}; set sdb.allow_changefeed_shrink to apply it");
+                continue;
+            }
+        };
+        db.query(&statements)
+            .await
+            .map_err(Error::Schema)?
+            .check()
+            .map_err(Error::Schema)?;
+        tracing::info!("schema: applied {step}");
+    }
+    Ok(plan)
+}
+
+/// The applied schema version (0 for a new or pre-versioning database) and
+/// whether the secured migrations ran.
+async fn current_version(db: &Surreal<any::Any>) -> Result<(u32, bool)> {
+    let mut response = db
+        .query(format!(
+            "SELECT VALUE version FROM ONLY {SCHEMA_VERSION_TABLE}:current;
+            SELECT VALUE secured FROM ONLY {SCHEMA_VERSION_TABLE}:current;"
+        ))
+        .await
+        .map_err(Error::Schema)?;
+    let version: Option<i64> = response.take(0).map_err(Error::Schema)?;
+    let secured: Option<bool> = response.take(1).map_err(Error::Schema)?;
+    Ok((
+        version.map_or(0, |v| u32::try_from(v).unwrap_or(u32::MAX)),
+        secured.unwrap_or(false),
+    ))
+}
+
+/// The current `CHANGEFEED` window of each table in [`CHANGEFEED_TABLES`]
+/// that exists.
+async fn changefeed_windows(db: &Surreal<any::Any>) -> Result<Vec<(&'static str, Option<u64>)>> {
+    let info: Option<Value> = db
+        .query("INFO FOR DB")
+        .await
+        .map_err(Error::Schema)?
+        .take(0)
+        .map_err(Error::Schema)?;
+    let Some(Value::Object(info)) = info else {
+        return Ok(Vec::new());
+    };
+    let Some(Value::Object(tables)) = info.get("tables") else {
+        return Ok(Vec::new());
+    };
+    Ok(CHANGEFEED_TABLES
+        .iter()
+        .filter_map(|&table| match tables.get(table) {
+            Some(Value::String(definition)) => Some((table, changefeed_secs(definition))),
+            _ => None,
+        })
+        .collect())
+}
+
+/// The `CHANGEFEED` window, in seconds, of a `DEFINE TABLE` statement as
+/// `INFO FOR DB` prints it; `None` without one.
+fn changefeed_secs(definition: &str) -> Option<u64> {
+    let mut words = definition.split_whitespace();
+    words.find(|w| w.eq_ignore_ascii_case("CHANGEFEED"))?;
+    duration_secs(words.next()?)
+}
+
+/// Whole seconds in a SurrealQL duration such as `1d`, `90s` or `1w2d3h`.
+/// Sub-second parts are dropped.
+fn duration_secs(duration: &str) -> Option<u64> {
+    let mut total = 0u64;
+    let mut rest = duration;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let amount: u64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let secs = match &rest[..unit_len] {
+            "y" => 365 * 86_400,
+            "w" => 7 * 86_400,
+            "d" => 86_400,
+            "h" => 3_600,
+            "m" => 60,
+            "s" => 1,
+            "ms" | "us" | "µs" | "ns" => 0,
+            _ => return None,
+        };
+        total = total.checked_add(amount.checked_mul(secs)?)?;
+        rest = &rest[unit_len..];
+    }
+    Some(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrations_are_ordered() {
+        assert!(
+            MIGRATIONS.windows(2).all(|w| w[0].version < w[1].version),
+            "MIGRATIONS must be strictly increasing by version"
+        );
+        assert_eq!(MIGRATIONS[0].version, 1);
+    }
+
+    #[test]
+    fn secured_steps_follow_the_setting() {
+        let versions = |from, db_secured, secured| {
+            pending(from, db_secured, secured)
+                .map(|m| m.version)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(versions(0, false, false), [1, 2, 3]);
+        assert_eq!(versions(0, false, true), [1, 2, 3]);
+        assert!(versions(latest(), false, false).is_empty());
+        assert_eq!(
+            versions(latest(), false, true),
+            [3],
+            "securing later replays"
+        );
+        assert!(versions(latest(), true, true).is_empty());
+        assert_eq!(latest_secured(), Some(3));
+    }
+
+    #[test]
+    fn parses_changefeed_windows() {
+        assert_eq!(
+            changefeed_secs(
+                "DEFINE TABLE message TYPE RELATION IN agent OUT agent SCHEMALESS CHANGEFEED 1d PERMISSIONS NONE"
+            ),
+            Some(86_400)
+        );
+        assert_eq!(
+            changefeed_secs("DEFINE TABLE message CHANGEFEED 1w2d3h4m5s INCLUDE ORIGINAL"),
+            Some(9 * 86_400 + 3 * 3_600 + 4 * 60 + 5)
+        );
+        assert_eq!(
+            changefeed_secs("DEFINE TABLE message CHANGEFEED 90s500ms"),
+            Some(90)
+        );
+        assert_eq!(changefeed_secs("DEFINE TABLE message SCHEMALESS"), None);
+        assert_eq!(
+            changefeed_secs("DEFINE TABLE message CHANGEFEED 1fortnight"),
+            None
+        );
+    }
+}
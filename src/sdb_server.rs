@@ -1,144 +1,419 @@
+use crate::client;
 use crate::error::{Error, Result};
-use crate::settings::SETTINGS;
-use bollard::Docker;
+use crate::settings::{self, Auth, PullPolicy, Settings, Storage};
+use bollard::container::LogOutput;
 use bollard::models::ContainerCreateBody;
 use bollard::models::HostConfig;
 use bollard::query_parameters::{
-    CreateContainerOptionsBuilder, CreateImageOptionsBuilder, StartContainerOptionsBuilder,
+    CreateContainerOptionsBuilder, CreateImageOptionsBuilder, InspectContainerOptions,
+    LogsOptionsBuilder, StartContainerOptionsBuilder,
 };
 use bollard::service::PortBinding;
+use bollard::{API_DEFAULT_VERSION, Docker};
 use std::collections::HashMap;
 use std::default::Default;
-use tokio::sync::oneshot;
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
 use tokio::time::{Duration, sleep};
 use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::Instrument;
+use uuid::Uuid;
+
+/// Mount point of a volume or bind mount inside the container.
+const DATA_DIR: &str = "/data";
+
+/// Seconds a Docker API request may take (bollard's default).
+const DOCKER_TIMEOUT_SECS: u64 = 120;
+
+/// The socket `connect_with_unix_defaults` uses.
+const DOCKER_SOCKET: &str = "/var/run/docker.sock";
+
+/// What a container that never became healthy left behind, captured before
+/// it is stopped and removed. Carried by [`Error::HealthCheck`] and
+/// [`Error::StartupTimeout`]; empty when there is no container to inspect.
+#[derive(Debug, Default)]
+pub struct StartupDiagnostics {
+    /// Container state, e.g. `exited` or `running`.
+    pub status: Option<String>,
+    /// Exit code, once the container has stopped.
+    pub exit_code: Option<i64>,
+    /// The last `sdb.container.failure_log_lines` lines of its stdout/stderr.
+    pub logs: Vec<String>,
+}
+
+impl fmt::Display for StartupDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(status) = &self.status {
+            write!(f, "; container {status}")?;
+        }
+        if let Some(code) = self.exit_code {
+            write!(f, " with exit code {code}")?;
+        }
+        if !self.logs.is_empty() {
+            write!(f, "; last {} log lines:", self.logs.len())?;
+            for line in &self.logs {
+                write!(f, "\n  {line}")?;
+            }
+        }
+        Ok(())
+    }
+}
 
 pub struct SurrealDBContainer {
     docker: Docker,
+    settings: Settings,
+    /// Reusing a SurrealDB that was already running; see
+    /// [`SurrealDBContainer::start_and_wait`].
+    attached: bool,
+    /// Host port SurrealDB is published on: `sdb.port`, or the one Docker
+    /// picked under `docker.dynamic_port`.
+    host_port: u16,
+    /// Where published ports are reachable: a remote daemon's host, else
+    /// `sdb.host`.
+    reachable_host: String,
+    /// Owner of background work (log forwarding); see
+    /// [`SurrealDBContainer::new`].
+    tasks: TaskTracker,
+    /// Stops that work: a child of the caller's token, also cancelled by
+    /// [`stop`](Self::stop).
+    token: CancellationToken,
 }
 
 impl SurrealDBContainer {
-    pub async fn new() -> Result<Self> {
-        let docker = Docker::connect_with_unix_defaults()?;
-        Ok(Self { docker })
-    }
-
-    pub async fn start_and_wait(&self) -> Result<()> {
-        tracing::info!("Pulling Docker image...");
-        self.pull_image().await?;
-        // Remove any stale container from a prior crashed run. Idempotent:
-        // silently succeeds if no container with that name exists.
-        self.remove_if_exists().await;
-        tracing::info!("Creating and starting container...");
-        self.create_and_start_container().await?;
+    /// A container for `settings.sdb` (image, port, credentials, name); see
+    /// [`container_name`] for the name it runs under.
+    ///
+    /// Background work such as `sdb.container.stream_logs` is spawned on
+    /// `tasks` and stops when `token` is cancelled or the container is
+    /// [`stop`](Self::stop)ped, so closing and waiting on `tasks` after
+    /// `stop` leaves nothing running.
+    pub async fn new(
+        mut settings: Settings,
+        tasks: &TaskTracker,
+        token: &CancellationToken,
+    ) -> Result<Self> {
+        let docker = connect(&settings.docker)?;
+        settings.sdb.container_name = container_name(&settings);
+        let host_port = settings.sdb.port;
+        let reachable_host = settings
+            .docker
+            .remote_host()
+            .unwrap_or_else(|| settings.sdb.host.clone());
+        Ok(Self {
+            docker,
+            settings,
+            attached: false,
+            host_port,
+            reachable_host,
+            tasks: tasks.clone(),
+            token: token.child_token(),
+        })
+    }
+
+    /// The settings to connect with. Once started under
+    /// `docker.dynamic_port` or on a remote daemon, `sdb.endpoint` points at
+    /// the published port on the host that actually serves it.
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// Start the container and wait until SurrealDB answers its health check.
+    ///
+    /// With `docker.attach`, a SurrealDB already serving `sdb.host:sdb.port` —
+    /// an external dev server, or a running container named
+    /// `sdb.container_name` with the configured image — is reused instead:
+    /// nothing is pulled, removed or created, and [`stop`](Self::stop) leaves
+    /// it running. Otherwise (and by default) a fresh container is created.
+    pub async fn start_and_wait(&mut self) -> Result<()> {
+        if self.settings.docker.attach && self.attach().await? {
+            self.attached = true;
+            tracing::info!(
+                "Attached to SurrealDB at {}:{}",
+                self.settings.sdb.host,
+                self.settings.sdb.port
+            );
+            return Ok(());
+        }
+
+        self.ensure_image().await?;
+        if self.settings.docker.keep_container && self.start_existing().await? {
+            tracing::info!("Reusing container {}", self.settings.sdb.container_name);
+        } else {
+            // Remove any stale container from a prior crashed run. Idempotent:
+            // silently succeeds if no container with that name exists.
+            self.remove_if_exists().await;
+            tracing::info!("Creating and starting container...");
+            self.create_and_start_container().await?;
+        }
+        if self.settings.sdb.container.stream_logs {
+            self.stream_logs();
+        }
+        if self.settings.docker.dynamic_port {
+            self.resolve_host_port().await?;
+        }
+        if self.settings.docker.dynamic_port || self.settings.docker.remote_host().is_some() {
+            self.settings.sdb.endpoint = format!("ws://{}:{}", self.reachable_host, self.host_port);
+        }
         tracing::info!("Container started, waiting for health check...");
+        let result = self.wait_healthy().await;
+        self.with_diagnostics(result).await
+    }
 
-        let (tx, rx) = oneshot::channel();
-        let url = format!("http://{}:{}/health", SETTINGS.sdb.host, SETTINGS.sdb.port);
-        let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(1))
-            .build()?;
-
-        tokio::spawn(async move {
-            let mut attempts = 25;
-            while attempts > 0 {
-                match client.get(&url).send().await {
-                    Ok(resp) if resp.status().is_success() => {
-                        tracing::debug!("SurrealDB health check succeeded");
-                        let _ = tx.send(());
-                        return;
-                    }
-                    Ok(resp) => {
-                        tracing::debug!("Health check returned status: {}", resp.status());
-                        attempts -= 1;
-                    }
-                    Err(e) => {
-                        tracing::debug!("Health check failed: {}", e);
-                        attempts -= 1;
+    /// Whether there is a SurrealDB to attach to: the endpoint is healthy
+    /// already, or our container is running the configured image and becomes
+    /// healthy. A container of that name running another image is an error
+    /// rather than something to remove — it may be someone else's database.
+    async fn attach(&self) -> Result<bool> {
+        if self.is_healthy().await {
+            return Ok(true);
+        }
+        let name = self.settings.sdb.container_name.as_str();
+        let found = match self
+            .docker
+            .inspect_container(name, None::<InspectContainerOptions>)
+            .await
+        {
+            Ok(inspect) => Some(FoundContainer {
+                running: inspect.state.and_then(|s| s.running).unwrap_or(false),
+                image: inspect.config.and_then(|c| c.image).unwrap_or_default(),
+            }),
+            Err(bollard::errors::Error::DockerResponseServerError {
+                status_code: 404, ..
+            }) => None,
+            Err(e) => return Err(e.into()),
+        };
+        let expected = format!("{}:{}", self.settings.sdb.image, self.settings.sdb.tag);
+        if !attach_to(name, &expected, found)? {
+            return Ok(false);
+        }
+        tracing::info!("Found running container {name}, waiting for health check...");
+        let result = self.wait_healthy().await;
+        self.with_diagnostics(result).await?;
+        Ok(true)
+    }
+
+    fn health_url(&self) -> String {
+        format!("http://{}:{}/health", self.reachable_host, self.host_port)
+    }
+
+    /// Read the host port Docker published `sdb.port` on.
+    async fn resolve_host_port(&mut self) -> Result<()> {
+        let name = self.settings.sdb.container_name.clone();
+        let inspect = self
+            .docker
+            .inspect_container(&name, None::<InspectContainerOptions>)
+            .await?;
+        let host_port = inspect
+            .network_settings
+            .and_then(|n| n.ports)
+            .and_then(|ports| published_port(ports, self.settings.sdb.port))
+            .ok_or(Error::HostPort { container: name })?;
+        self.host_port = host_port;
+        tracing::info!("SurrealDB published on host port {host_port}");
+        Ok(())
+    }
+
+    /// One health probe, without retries.
+    async fn is_healthy(&self) -> bool {
+        let Ok(client) = reqwest::Client::builder()
+            .timeout(Duration::from_millis(
+                self.settings.sdb.health.probe_timeout_ms,
+            ))
+            .build()
+        else {
+            return false;
+        };
+        matches!(
+            client.get(self.health_url()).send().await,
+            Ok(resp) if resp.status().is_success()
+        )
+    }
+
+    /// Retry [`is_ready`](Self::is_ready) as `sdb.health` says until it
+    /// passes.
+    async fn wait_healthy(&self) -> Result<()> {
+        let health = &self.settings.sdb.health;
+        let attempts = async {
+            for attempt in 1..=health.attempts {
+                match self.is_ready().await {
+                    Ok(()) => return true,
+                    Err(reason) => {
+                        tracing::debug!("Readiness attempt {attempt} failed: {reason}")
                     }
                 }
-                if attempts > 0 {
-                    sleep(Duration::from_millis(1000)).await;
+                if attempt < health.attempts {
+                    sleep(Duration::from_millis(health.interval_ms)).await;
                 }
             }
-            // Ensure the channel is closed if we run out of attempts
-            drop(tx);
-        });
-
-        // Wait for ready signal or timeout
-        match tokio::time::timeout(Duration::from_secs(30), rx).await {
-            Ok(Ok(())) => {
-                // Give the WebSocket endpoint a moment to be fully ready
-                sleep(Duration::from_millis(500)).await;
+            false
+        };
+        let limit = Duration::from_secs(health.startup_timeout_secs);
+        match tokio::time::timeout(limit, attempts).await {
+            Ok(true) => {
                 tracing::info!("SurrealDB is ready and accepting connections");
                 Ok(())
             }
-            Ok(Err(_)) => Err(Error::HealthCheck),
-            Err(_) => Err(Error::StartupTimeout),
+            Ok(false) => Err(Error::HealthCheck {
+                diagnostics: StartupDiagnostics::default(),
+            }),
+            Err(_) => Err(Error::StartupTimeout {
+                diagnostics: StartupDiagnostics::default(),
+            }),
+        }
+    }
+
+    /// One readiness check: `/health` answers, then a root session on
+    /// `sdb.endpoint` runs a query. The first alone can pass before the
+    /// WebSocket RPC endpoint accepts sessions.
+    async fn is_ready(&self) -> std::result::Result<(), String> {
+        if !self.is_healthy().await {
+            return Err(format!("{} did not answer", self.health_url()));
+        }
+        client::check_ready(&self.settings, &Auth::Root)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// Attach the container's state and last log lines to a failed health
+    /// wait. Must run before [`stop`](Self::stop), which removes the container.
+    async fn with_diagnostics(&self, result: Result<()>) -> Result<()> {
+        match result {
+            Err(Error::HealthCheck { .. }) => Err(Error::HealthCheck {
+                diagnostics: self.diagnostics().await,
+            }),
+            Err(Error::StartupTimeout { .. }) => Err(Error::StartupTimeout {
+                diagnostics: self.diagnostics().await,
+            }),
+            result => result,
+        }
+    }
+
+    /// Inspect the container and read the tail of its logs, best-effort: what
+    /// cannot be read is left out.
+    async fn diagnostics(&self) -> StartupDiagnostics {
+        let name = self.settings.sdb.container_name.as_str();
+        let mut diagnostics = StartupDiagnostics::default();
+        match self
+            .docker
+            .inspect_container(name, None::<InspectContainerOptions>)
+            .await
+        {
+            Ok(inspect) => {
+                if let Some(state) = inspect.state {
+                    diagnostics.status = state.status.map(|status| status.to_string());
+                    if !state.running.unwrap_or(false) {
+                        diagnostics.exit_code = state.exit_code;
+                    }
+                }
+            }
+            Err(e) => tracing::debug!("inspecting failed container {name}: {e}"),
+        }
+
+        let lines = self.settings.sdb.container.failure_log_lines;
+        if lines == 0 {
+            return diagnostics;
+        }
+        let options = LogsOptionsBuilder::default()
+            .stdout(true)
+            .stderr(true)
+            .tail(&lines.to_string())
+            .build();
+        let mut logs = self.docker.logs(name, Some(options));
+        while let Some(output) = logs.next().await {
+            match output {
+                Ok(output) => diagnostics
+                    .logs
+                    .extend(output.to_string().lines().map(str::to_string)),
+                Err(e) => {
+                    tracing::debug!("reading logs of failed container {name}: {e}");
+                    break;
+                }
+            }
+        }
+        let excess = diagnostics.logs.len().saturating_sub(lines);
+        diagnostics.logs.drain(..excess);
+        diagnostics
+    }
+
+    /// Make `sdb.image:sdb.tag` available as `docker.pull_policy` says; see
+    /// [`image_plan`].
+    async fn ensure_image(&self) -> Result<()> {
+        let image = format!("{}:{}", self.settings.sdb.image, self.settings.sdb.tag);
+        let present = self.image_present(&image).await?;
+        match image_plan(self.settings.docker.pull_policy, present) {
+            ImagePlan::UseLocal => {
+                tracing::info!("Using local image {image}");
+                Ok(())
+            }
+            ImagePlan::Missing => Err(Error::ImagePull {
+                image,
+                reason: "not present locally and docker.pull_policy is never".to_string(),
+            }),
+            ImagePlan::Pull { fallback } => pull_outcome(self.pull_image(&image).await, fallback),
+        }
+    }
+
+    async fn image_present(&self, image: &str) -> Result<bool> {
+        match self.docker.inspect_image(image).await {
+            Ok(_) => Ok(true),
+            Err(bollard::errors::Error::DockerResponseServerError {
+                status_code: 404, ..
+            }) => Ok(false),
+            Err(e) => Err(e.into()),
         }
     }
 
-    async fn pull_image(&self) -> Result<()> {
+    /// Pull `image`, reporting each layer's status changes at info level.
+    async fn pull_image(&self, image: &str) -> Result<()> {
+        let pull_err = |reason: String| Error::ImagePull {
+            image: image.to_string(),
+            reason,
+        };
+        tracing::info!("Pulling image {image}...");
         let mut builder = CreateImageOptionsBuilder::default()
-            .from_image(&SETTINGS.sdb.image)
-            .tag(&SETTINGS.sdb.tag);
-        if let Some(p) = &SETTINGS.docker.platform {
+            .from_image(&self.settings.sdb.image)
+            .tag(&self.settings.sdb.tag);
+        if let Some(p) = &self.settings.docker.platform {
             builder = builder.platform(p);
         }
         let create_image_options = builder.build();
         let mut stream = self
             .docker
             .create_image(Some(create_image_options), None, None);
+        // Last status per layer: `Downloading`/`Extracting` repeat with every
+        // progress tick, so only transitions are logged.
+        let mut layers: HashMap<String, String> = HashMap::new();
         while let Some(pull_result) = stream.next().await {
-            tracing::trace!("Pulling image: {:?}", pull_result);
+            let info = pull_result.map_err(|e| pull_err(e.to_string()))?;
+            tracing::trace!("Pulling image: {:?}", info);
+            if let Some(detail) = info.error_detail.and_then(|d| d.message) {
+                return Err(pull_err(detail));
+            }
+            let Some(status) = info.status else {
+                continue;
+            };
+            match info.id {
+                Some(id) => {
+                    if layers.get(&id) != Some(&status) {
+                        tracing::info!("{image}: {id}: {status}");
+                        layers.insert(id, status);
+                    }
+                }
+                None => tracing::info!("{image}: {status}"),
+            }
         }
+        tracing::info!("Pulled image {image}");
         Ok(())
     }
 
     async fn create_and_start_container(&self) -> Result<()> {
-        let bind_address = format!("0.0.0.0:{}", SETTINGS.sdb.port);
-        let cmd: Vec<String> = vec![
-            "start".to_string(),
-            "--log".to_string(),
-            "trace".to_string(),
-            "-u".to_string(),
-            SETTINGS.sdb.username.to_string(),
-            "-p".to_string(),
-            SETTINGS.sdb.password.to_string(),
-            "-b".to_string(),
-            bind_address,
-            "memory".to_string(),
-        ];
-
-        let port_bindings = {
-            let mut port_map = HashMap::new();
-            port_map.insert(
-                format!("{}/tcp", SETTINGS.sdb.port),
-                Some(vec![PortBinding {
-                    host_ip: None,
-                    host_port: Some(format!("{}", SETTINGS.sdb.port)),
-                }]),
-            );
-            port_map
-        };
-
-        let host_config = HostConfig {
-            port_bindings: Some(port_bindings),
-            auto_remove: Some(true), // This will remove the container when it stops
-            ..Default::default()
-        };
-
-        let container_body = ContainerCreateBody {
-            image: Some(format!("{}:{}", SETTINGS.sdb.image, SETTINGS.sdb.tag)),
-            cmd: Some(cmd),
-            exposed_ports: Some(vec![format!("{}/tcp", SETTINGS.sdb.port)]),
-            host_config: Some(host_config),
-            ..Default::default()
-        };
-
+        let container_body = container_body(&self.settings);
         let mut builder =
-            CreateContainerOptionsBuilder::default().name(&SETTINGS.sdb.container_name);
-        if let Some(p) = &SETTINGS.docker.platform {
+            CreateContainerOptionsBuilder::default().name(&self.settings.sdb.container_name);
+        if let Some(p) = &self.settings.docker.platform {
             builder = builder.platform(p);
         }
         let create_container_options = builder.build();
@@ -153,18 +428,94 @@ impl SurrealDBContainer {
         Ok(())
     }
 
+    /// Forward the container's stdout/stderr into tracing, in an
+    /// `sdb_container` span, on `tasks` until the container stops or `token`
+    /// is cancelled.
+    fn stream_logs(&self) {
+        let name = self.settings.sdb.container_name.clone();
+        let options = LogsOptionsBuilder::default()
+            .follow(true)
+            .stdout(true)
+            .stderr(true)
+            .build();
+        let mut logs = self.docker.logs(&name, Some(options));
+        let span = tracing::info_span!("sdb_container", container = %name);
+        let token = self.token.clone();
+        self.tasks.spawn(
+            async move {
+                loop {
+                    let output = tokio::select! {
+                        biased;
+                        _ = token.cancelled() => break,
+                        output = logs.next() => output,
+                    };
+                    match output {
+                        None => break,
+                        Some(Ok(output)) => {
+                            let stream = match output {
+                                LogOutput::StdErr { .. } => "stderr",
+                                _ => "stdout",
+                            };
+                            tracing::info!(
+                                target: "sdb_container",
+                                stream,
+                                "{}",
+                                output.to_string().trim_end()
+                            );
+                        }
+                        Some(Err(e)) => {
+                            tracing::debug!("container log stream ended: {e}");
+                            break;
+                        }
+                    }
+                }
+            }
+            .instrument(span),
+        );
+    }
+
+    /// Start the container named `sdb.container_name` if it exists and is not
+    /// running. Returns `false` if there is none.
+    async fn start_existing(&self) -> Result<bool> {
+        let name = self.settings.sdb.container_name.as_str();
+        let inspect = match self
+            .docker
+            .inspect_container(name, None::<InspectContainerOptions>)
+            .await
+        {
+            Ok(inspect) => inspect,
+            Err(bollard::errors::Error::DockerResponseServerError {
+                status_code: 404, ..
+            }) => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        let running = inspect
+            .state
+            .and_then(|state| state.running)
+            .unwrap_or(false);
+        if !running {
+            self.docker
+                .start_container(name, Some(StartContainerOptionsBuilder::default().build()))
+                .await?;
+        }
+        Ok(true)
+    }
+
     /// Force-remove any container using our configured name. Called at startup
-    /// to recover from a crashed previous run whose `auto_remove` didn't fire.
+    /// to recover from a crashed previous run that never reached `stop`.
     async fn remove_if_exists(&self) {
         let opts = bollard::query_parameters::RemoveContainerOptionsBuilder::default()
             .force(true)
             .build();
         match self
             .docker
-            .remove_container(SETTINGS.sdb.container_name.as_str(), Some(opts))
+            .remove_container(self.settings.sdb.container_name.as_str(), Some(opts))
             .await
         {
-            Ok(()) => tracing::info!("Removed stale container {}", SETTINGS.sdb.container_name),
+            Ok(()) => tracing::info!(
+                "Removed stale container {}",
+                self.settings.sdb.container_name
+            ),
             Err(bollard::errors::Error::DockerResponseServerError {
                 status_code: 404, ..
             }) => {
@@ -174,11 +525,20 @@ impl SurrealDBContainer {
         }
     }
 
+    /// Stop the container and remove it unless `docker.keep_container` is
+    /// set; an attached one is left running. Either way its background tasks
+    /// are cancelled afterwards.
     pub async fn stop(&self) -> Result<()> {
+        let policy = stop_policy(self.attached, self.settings.docker.keep_container);
+        if policy == StopPolicy::Leave {
+            self.token.cancel();
+            tracing::info!("Leaving attached SurrealDB running");
+            return Ok(());
+        }
         match self
             .docker
             .stop_container(
-                SETTINGS.sdb.container_name.as_str(),
+                self.settings.sdb.container_name.as_str(),
                 Some(bollard::query_parameters::StopContainerOptionsBuilder::default().build()),
             )
             .await
@@ -190,6 +550,460 @@ impl SurrealDBContainer {
                 tracing::warn!("docker stop failed: {:?}", error);
             }
         }
+        if policy == StopPolicy::StopAndRemove
+            && let Err(error) = self
+                .docker
+                .remove_container(
+                    self.settings.sdb.container_name.as_str(),
+                    Some(
+                        bollard::query_parameters::RemoveContainerOptionsBuilder::default().build(),
+                    ),
+                )
+                .await
+        {
+            tracing::warn!("docker remove failed: {:?}", error);
+        }
+        // After the stop, so the container's shutdown output is forwarded.
+        self.token.cancel();
         Ok(())
     }
 }
+
+/// What [`SurrealDBContainer`] creates for `settings`: `surreal start` with
+/// the root credentials and `sdb.container` options, `sdb.port` published
+/// (on a port Docker picks under `docker.dynamic_port`), and the data on
+/// `docker.storage`.
+fn container_body(settings: &Settings) -> ContainerCreateBody {
+    let bind_address = format!("0.0.0.0:{}", settings.sdb.port);
+    let mut cmd: Vec<String> = vec![
+        "start".to_string(),
+        "--log".to_string(),
+        settings.sdb.container.log_level.clone(),
+        "-u".to_string(),
+        settings.sdb.username.to_string(),
+        "-p".to_string(),
+        settings.sdb.password.to_string(),
+        "-b".to_string(),
+        bind_address,
+    ];
+    let (engine, binds) = match &settings.docker.storage {
+        Storage::Memory => ("memory".to_string(), None),
+        Storage::Volume { name: source } | Storage::Bind { path: source } => (
+            format!("surrealkv://{DATA_DIR}/sdb"),
+            Some(vec![format!("{source}:{DATA_DIR}")]),
+        ),
+    };
+    let options = &settings.sdb.container;
+    cmd.extend(options.args.iter().cloned());
+    cmd.push(engine);
+
+    let port_bindings = {
+        let mut port_map = HashMap::new();
+        port_map.insert(
+            format!("{}/tcp", settings.sdb.port),
+            Some(vec![PortBinding {
+                host_ip: None,
+                // No host port: Docker picks a free one.
+                host_port: (!settings.docker.dynamic_port)
+                    .then(|| format!("{}", settings.sdb.port)),
+            }]),
+        );
+        port_map
+    };
+
+    let host_config = HostConfig {
+        port_bindings: Some(port_bindings),
+        binds,
+        // Not `auto_remove`: a container that exits during startup must
+        // outlive its exit so its logs can be read. `stop` removes it.
+        auto_remove: Some(false),
+        memory: options.memory_bytes(),
+        nano_cpus: options.cpus.map(|cpus| (cpus * 1e9) as i64),
+        ..Default::default()
+    };
+
+    ContainerCreateBody {
+        image: Some(format!("{}:{}", settings.sdb.image, settings.sdb.tag)),
+        cmd: Some(cmd),
+        exposed_ports: Some(vec![format!("{}/tcp", settings.sdb.port)]),
+        host_config: Some(host_config),
+        env: Some(
+            options
+                .env
+                .iter()
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect(),
+        ),
+        labels: Some(options.labels.clone().into_iter().collect()),
+        ..Default::default()
+    }
+}
+
+/// How [`SurrealDBContainer::ensure_image`] gets the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ImagePlan {
+    /// Use the local copy without pulling.
+    UseLocal,
+    /// Pull it; with `fallback`, a local copy exists to fall back on if the
+    /// pull fails.
+    Pull { fallback: bool },
+    /// Absent and not to be pulled: [`Error::ImagePull`].
+    Missing,
+}
+
+/// `docker.pull_policy` × whether the image is `present` locally.
+/// `if-not-present` and `never` use a local copy; `always` pulls anyway and,
+/// if the pull fails, falls back to that copy with a warning (see
+/// [`pull_outcome`]), so an offline machine still starts. Without a local
+/// copy, `never` is an error and the others pull.
+fn image_plan(policy: PullPolicy, present: bool) -> ImagePlan {
+    match (policy, present) {
+        (PullPolicy::IfNotPresent | PullPolicy::Never, true) => ImagePlan::UseLocal,
+        (PullPolicy::Never, false) => ImagePlan::Missing,
+        (PullPolicy::Always, true) => ImagePlan::Pull { fallback: true },
+        (_, false) => ImagePlan::Pull { fallback: false },
+    }
+}
+
+/// The result of an [`ImagePlan::Pull`]: a failed pull is returned unless
+/// there is a local copy to `fallback` on.
+fn pull_outcome(pulled: Result<()>, fallback: bool) -> Result<()> {
+    match pulled {
+        Err(e) if fallback => {
+            tracing::warn!("{e}; using the local copy");
+            Ok(())
+        }
+        pulled => pulled,
+    }
+}
+
+/// A container with our name, as `docker inspect` reports it.
+#[derive(Debug)]
+struct FoundContainer {
+    running: bool,
+    image: String,
+}
+
+/// Whether [`SurrealDBContainer::attach`] may reuse `found`, the container
+/// named `name`, when the endpoint is not healthy yet: only while it runs
+/// the `expected` image. With none running it creates one instead; one
+/// running another image is an error rather than something to remove.
+fn attach_to(name: &str, expected: &str, found: Option<FoundContainer>) -> Result<bool> {
+    match found {
+        Some(found) if found.running && found.image != expected => Err(Error::Config {
+            reason: format!(
+                "container {name} is running {}, not {expected}; \
+                 stop it or change sdb.container_name",
+                found.image
+            ),
+        }),
+        Some(found) => Ok(found.running),
+        None => Ok(false),
+    }
+}
+
+/// What [`SurrealDBContainer::stop`] does with the container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StopPolicy {
+    /// Attached to someone else's SurrealDB: leave it running.
+    Leave,
+    /// `docker.keep_container`: stop it, keep it for the next run.
+    Stop,
+    /// Stop and remove it. Containers are created without `auto_remove`, so
+    /// this is what cleans them up.
+    StopAndRemove,
+}
+
+fn stop_policy(attached: bool, keep_container: bool) -> StopPolicy {
+    match (attached, keep_container) {
+        (true, _) => StopPolicy::Leave,
+        (false, true) => StopPolicy::Stop,
+        (false, false) => StopPolicy::StopAndRemove,
+    }
+}
+
+/// The name the container runs under: `sdb.container_name`, with a per-run
+/// suffix under `docker.dynamic_port` so concurrent runs never share or
+/// remove each other's container. With `docker.keep_container` the name stays
+/// stable, so the next run reuses the kept container instead of leaving one
+/// behind per run; concurrent runs then need distinct `sdb.container_name`s.
+fn container_name(settings: &Settings) -> String {
+    let name = &settings.sdb.container_name;
+    if settings.docker.dynamic_port && !settings.docker.keep_container {
+        let suffix = Uuid::new_v4().simple().to_string();
+        format!("{name}_{}", &suffix[..8])
+    } else {
+        name.clone()
+    }
+}
+
+/// The host port `ports` (a container's published ports) maps `port/tcp` to.
+fn published_port(mut ports: HashMap<String, Option<Vec<PortBinding>>>, port: u16) -> Option<u16> {
+    ports
+        .remove(&format!("{port}/tcp"))
+        .flatten()?
+        .into_iter()
+        .find_map(|binding| binding.host_port)?
+        .parse()
+        .ok()
+}
+
+/// Connect to the daemon `docker` selects: its `host` (a socket path, plain
+/// HTTP, or TLS when asked for), else the local Docker socket, else a
+/// rootless Podman socket.
+fn connect(docker: &settings::Docker) -> Result<Docker> {
+    let Some(host) = docker.daemon_host() else {
+        return match podman_socket() {
+            Some(socket) if !Path::new(DOCKER_SOCKET).exists() => {
+                tracing::info!("Using Podman socket {}", socket.display());
+                Ok(Docker::connect_with_socket(
+                    &socket.to_string_lossy(),
+                    DOCKER_TIMEOUT_SECS,
+                    API_DEFAULT_VERSION,
+                )?)
+            }
+            _ => Ok(Docker::connect_with_unix_defaults()?),
+        };
+    };
+    if host.starts_with("unix://") || host.starts_with('/') {
+        return Ok(Docker::connect_with_socket(
+            &host,
+            DOCKER_TIMEOUT_SECS,
+            API_DEFAULT_VERSION,
+        )?);
+    }
+    let tls = docker.tls || env::var("DOCKER_TLS_VERIFY").is_ok_and(|v| !v.is_empty());
+    if tls {
+        let certs = docker
+            .cert_path
+            .clone()
+            .or_else(|| env::var("DOCKER_CERT_PATH").ok())
+            .map(PathBuf::from)
+            .or_else(|| {
+                env::var("HOME")
+                    .ok()
+                    .map(|home| Path::new(&home).join(".docker"))
+            })
+            .unwrap_or_default();
+        return Ok(Docker::connect_with_ssl(
+            &host,
+            &certs.join("key.pem"),
+            &certs.join("cert.pem"),
+            &certs.join("ca.pem"),
+            DOCKER_TIMEOUT_SECS,
+            API_DEFAULT_VERSION,
+        )?);
+    }
+    Ok(Docker::connect_with_http(
+        &host,
+        DOCKER_TIMEOUT_SECS,
+        API_DEFAULT_VERSION,
+    )?)
+}
+
+/// The rootless Podman socket, if one is running.
+fn podman_socket() -> Option<PathBuf> {
+    let socket = Path::new(&env::var("XDG_RUNTIME_DIR").ok()?).join("podman/podman.sock");
+    socket.exists().then_some(socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dynamic_names_are_per_run_unless_kept() {
+        let mut settings = Settings::default();
+        let base = settings.sdb.container_name.clone();
+        assert_eq!(container_name(&settings), base);
+
+        settings.docker.dynamic_port = true;
+        let (a, b) = (container_name(&settings), container_name(&settings));
+        assert!(a.starts_with(&format!("{base}_")), "got {a}");
+        assert_ne!(a, b, "parallel runs get distinct containers");
+
+        settings.docker.keep_container = true;
+        assert_eq!(
+            container_name(&settings),
+            base,
+            "a kept container is reused"
+        );
+    }
+
+    #[test]
+    fn storage_selects_engine_and_mount() {
+        let mut settings = Settings::default();
+        let body = container_body(&settings);
+        let host = body.host_config.expect("host config");
+        assert_eq!(host.binds, None);
+        assert_eq!(host.auto_remove, Some(false));
+        assert_eq!(
+            body.cmd.expect("cmd").last().map(String::as_str),
+            Some("memory")
+        );
+
+        settings.docker.storage = Storage::Volume {
+            name: "sdb_data".to_string(),
+        };
+        let body = container_body(&settings);
+        let host = body.host_config.expect("host config");
+        assert_eq!(host.binds, Some(vec![format!("sdb_data:{DATA_DIR}")]));
+        assert_eq!(
+            host.auto_remove,
+            Some(false),
+            "a failed container must outlive its exit for its logs"
+        );
+        assert_eq!(
+            body.cmd.expect("cmd").last().cloned(),
+            Some(format!("surrealkv://{DATA_DIR}/sdb"))
+        );
+
+        settings.docker.storage = Storage::Bind {
+            path: "/srv/sdb".to_string(),
+        };
+        let host = container_body(&settings).host_config.expect("host config");
+        assert_eq!(host.binds, Some(vec![format!("/srv/sdb:{DATA_DIR}")]));
+    }
+
+    #[test]
+    fn publishes_fixed_or_dynamic_port() {
+        let mut settings = Settings::default();
+        let port = format!("{}/tcp", settings.sdb.port);
+        let published = |settings: &Settings| {
+            container_body(settings)
+                .host_config
+                .and_then(|h| h.port_bindings)
+                .and_then(|mut ports| ports.remove(&port))
+                .flatten()
+                .and_then(|bindings| bindings.into_iter().next())
+                .and_then(|binding| binding.host_port)
+        };
+        assert_eq!(published(&settings), Some(settings.sdb.port.to_string()));
+        settings.docker.dynamic_port = true;
+        assert_eq!(published(&settings), None, "Docker picks the host port");
+    }
+
+    #[test]
+    fn diagnostics_display() {
+        let port_conflict = StartupDiagnostics {
+            status: Some("exited".to_string()),
+            exit_code: Some(1),
+            logs: vec!["Error: Address already in use (os error 98)".to_string()],
+        };
+        assert_eq!(
+            Error::HealthCheck {
+                diagnostics: port_conflict
+            }
+            .to_string(),
+            "SurrealDB health check failed after all attempts; container exited with \
+             exit code 1; last 1 log lines:\n  Error: Address already in use (os error 98)"
+        );
+
+        // A missing image fails before any container exists to inspect.
+        assert_eq!(StartupDiagnostics::default().to_string(), "");
+        assert_eq!(
+            Error::StartupTimeout {
+                diagnostics: StartupDiagnostics::default()
+            }
+            .to_string(),
+            "timed out waiting for SurrealDB to start"
+        );
+
+        let unhealthy = StartupDiagnostics {
+            status: Some("running".to_string()),
+            exit_code: None,
+            logs: vec![
+                "INFO Started kvs store".to_string(),
+                "WARN slow startup".to_string(),
+            ],
+        };
+        assert_eq!(
+            unhealthy.to_string(),
+            "; container running; last 2 log lines:\n  INFO Started kvs store\n  WARN slow startup"
+        );
+    }
+
+    #[test]
+    fn pull_policy_matrix() {
+        use PullPolicy::{Always, IfNotPresent, Never};
+        let cases = [
+            (Always, true, ImagePlan::Pull { fallback: true }),
+            (Always, false, ImagePlan::Pull { fallback: false }),
+            (IfNotPresent, true, ImagePlan::UseLocal),
+            (IfNotPresent, false, ImagePlan::Pull { fallback: false }),
+            (Never, true, ImagePlan::UseLocal),
+            (Never, false, ImagePlan::Missing),
+        ];
+        for (policy, present, plan) in cases {
+            assert_eq!(
+                image_plan(policy, present),
+                plan,
+                "{policy:?}, present={present}"
+            );
+        }
+
+        let failed = || {
+            Err(Error::ImagePull {
+                image: "surrealdb/surrealdb:v3".to_string(),
+                reason: "offline".to_string(),
+            })
+        };
+        pull_outcome(Ok(()), false).expect("pulled");
+        pull_outcome(Ok(()), true).expect("pulled");
+        pull_outcome(failed(), true).expect("falls back to the local copy");
+        assert!(matches!(
+            pull_outcome(failed(), false),
+            Err(Error::ImagePull { .. })
+        ));
+    }
+
+    #[test]
+    fn attaches_only_to_a_running_matching_container() {
+        let found = |running: bool, image: &str| {
+            Some(FoundContainer {
+                running,
+                image: image.to_string(),
+            })
+        };
+        let expected = "surrealdb/surrealdb:v3";
+        assert!(!attach_to("sdb", expected, None).expect("no container"));
+        assert!(!attach_to("sdb", expected, found(false, expected)).expect("stopped"));
+        assert!(attach_to("sdb", expected, found(true, expected)).expect("running"));
+        assert!(
+            !attach_to("sdb", expected, found(false, "postgres:16")).expect("stopped other"),
+            "a stopped container is replaced whatever its image"
+        );
+        match attach_to("sdb", expected, found(true, "postgres:16")) {
+            Err(Error::Config { reason }) => assert!(reason.contains("postgres:16"), "{reason}"),
+            other => panic!("expected Error::Config, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn stop_removes_unless_kept_or_attached() {
+        assert_eq!(stop_policy(false, false), StopPolicy::StopAndRemove);
+        assert_eq!(stop_policy(false, true), StopPolicy::Stop);
+        assert_eq!(stop_policy(true, false), StopPolicy::Leave);
+        assert_eq!(stop_policy(true, true), StopPolicy::Leave);
+    }
+
+    #[test]
+    fn reads_the_published_port() {
+        let binding = |host_port: Option<&str>| PortBinding {
+            host_ip: Some("0.0.0.0".to_string()),
+            host_port: host_port.map(str::to_string),
+        };
+        let ports = HashMap::from([
+            ("9000/tcp".to_string(), Some(vec![binding(Some("49153"))])),
+            (
+                "8000/tcp".to_string(),
+                Some(vec![binding(None), binding(Some("49154"))]),
+            ),
+        ]);
+        assert_eq!(published_port(ports.clone(), 8000), Some(49154));
+        assert_eq!(published_port(ports.clone(), 8001), None);
+
+        let unpublished = HashMap::from([("8000/tcp".to_string(), None)]);
+        assert_eq!(published_port(unpublished, 8000), None);
+    }
+}
@@ -1,22 +1,142 @@
-use config::{Config, ConfigError, Environment, File};
+use config::builder::{ConfigBuilder, DefaultState};
+use config::{Config, Environment, File};
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::env;
+use std::path::Path;
 use std::sync::LazyLock;
 
-pub static SETTINGS: LazyLock<Settings> =
-    LazyLock::new(|| Settings::new().expect("Failed to setup settings"));
+use crate::error::{Error, Result};
+
+/// Settings loaded by [`Settings::new`] on first use. Panics on first access
+/// if they fail to load or validate; [`Settings::global`] returns the error
+/// instead.
+pub static SETTINGS: LazyLock<Settings> = LazyLock::new(|| match &*LOADED {
+    Ok(settings) => settings.clone(),
+    Err(reason) => panic!("invalid configuration: {reason}"),
+});
+
+static LOADED: LazyLock<std::result::Result<Settings, String>> =
+    LazyLock::new(|| Settings::new().map_err(|e| e.to_string()));
+
+/// Values `environment` may take.
+const ENVIRONMENTS: [&str; 3] = ["development", "test", "production"];
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
 pub struct Logger {
     pub level: String,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+impl Default for Logger {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
 pub struct Docker {
     pub platform: Option<String>,
+    /// Daemon to manage the container through: `unix:///path/to.sock` (or a
+    /// bare socket path), `tcp://host:port` or `http://host:port`. When
+    /// unset, [`Settings::new`] fills it from `DOCKER_HOST`; otherwise the
+    /// local Docker socket is used, then the rootless Podman socket under
+    /// `$XDG_RUNTIME_DIR`.
+    pub host: Option<String>,
+    /// Talk TLS to a `tcp://` daemon, with `key.pem`, `cert.pem` and
+    /// `ca.pem` from `cert_path`. Also enabled by a non-empty
+    /// `DOCKER_TLS_VERIFY`.
+    pub tls: bool,
+    /// Directory holding the TLS client certificates. Unset falls back to
+    /// `DOCKER_CERT_PATH`, then `~/.docker`.
+    pub cert_path: Option<String>,
+    /// When to pull `sdb.image:sdb.tag`. Defaults to `if-not-present`.
+    pub pull_policy: PullPolicy,
+    /// Where the local container keeps its data. Defaults to memory, which
+    /// is lost when the container stops.
+    pub storage: Storage,
+    /// Reuse the container across runs instead of creating a fresh one:
+    /// it is not auto-removed when it stops, and an existing container with
+    /// `sdb.container_name` is started again rather than force-removed. With
+    /// `storage` on a volume or bind mount this keeps the data across
+    /// container restarts. An existing container is reused as it was created,
+    /// so remove it by hand after changing its settings.
+    pub keep_container: bool,
+    /// Reuse a SurrealDB that is already serving `sdb.host:sdb.port` — an
+    /// external dev server, or a running container named
+    /// `sdb.container_name` with the configured image — and leave it running
+    /// on shutdown. Falls back to starting a container when there is none.
+    /// Cannot be combined with `dynamic_port`. Off by default: every run gets
+    /// a fresh container.
+    pub attach: bool,
+    /// Publish the container's port on a free host port chosen by Docker
+    /// instead of `sdb.port`, and give the container a unique per-run name,
+    /// so several test binaries or developers can run side by side. The
+    /// endpoint is derived from the published port; read it from the
+    /// connected client's settings. With `keep_container` the name stays
+    /// `sdb.container_name` so the kept container is reused. Off by default.
+    pub dynamic_port: bool,
+}
+
+impl Docker {
+    /// `host`, unless empty.
+    pub fn daemon_host(&self) -> Option<String> {
+        self.host.clone().filter(|host| !host.is_empty())
+    }
+
+    /// Hostname of a daemon reached over the network (`tcp://`, `http://`
+    /// or `https://`), where containers publish their ports; `None` for a
+    /// local socket.
+    pub fn remote_host(&self) -> Option<String> {
+        let host = self.daemon_host()?;
+        let (scheme, rest) = host.split_once("://")?;
+        if !matches!(scheme, "tcp" | "http" | "https") {
+            return None;
+        }
+        let authority = rest.split('/').next().unwrap_or_default();
+        let hostname = match authority.rsplit_once(':') {
+            Some((hostname, port)) if !port.contains(']') => hostname,
+            _ => authority,
+        };
+        let hostname = hostname.trim_start_matches('[').trim_end_matches(']');
+        (!hostname.is_empty()).then(|| hostname.to_string())
+    }
+}
+
+/// `docker.pull_policy` values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PullPolicy {
+    /// Pull on every start. If the pull fails and a local copy exists, that
+    /// copy is used with a warning; without one the pull error is returned.
+    Always,
+    /// Pull only when the image is not present locally.
+    #[default]
+    IfNotPresent,
+    /// Never pull; a missing image is [`Error::ImagePull`].
+    Never,
+}
+
+/// Storage backend of the local container, selected by its `kind` key
+/// (`DOCKER__STORAGE__KIND` in the environment). The file-backed kinds run
+/// the `surrealkv` engine on `/data` inside the container.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Storage {
+    #[default]
+    Memory,
+    /// A named Docker volume, created on first use.
+    Volume { name: String },
+    /// A host directory, bind-mounted. It must be writable by the
+    /// container's user.
+    Bind { path: String },
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
 pub struct Sdb {
     pub host: String,
     pub port: u16,
@@ -25,25 +145,243 @@ pub struct Sdb {
     pub container_name: String,
     pub namespace: String,
     pub database: String,
+    /// Root credentials. A local container is started with them, and they are
+    /// what the client signs in with under the default `auth` mode.
     pub username: String,
     pub password: String,
+    /// How the client authenticates against a server. Defaults to root with
+    /// `username`/`password`; ignored for an embedded engine.
+    pub auth: Auth,
+    /// Re-authenticate every this many seconds so a long-running process
+    /// never holds an expired session. Set it below the session/token
+    /// duration of the user or access method in use. Unset (the default)
+    /// signs in once per connection.
+    pub auth_refresh_secs: Option<u64>,
+    /// Define the secured schema: row-level `PERMISSIONS` on every bus table
+    /// and the `agent` record access method, so agents signed in as
+    /// themselves (see `crate::identity`) can only send as, read for and
+    /// move the cursor of their own agent. Off by default.
+    pub secured: bool,
+    /// Apply pending schema migrations when a client connects (see
+    /// `crate::migrate`). Turn off to review `migrate::plan` and run
+    /// `migrate::apply` by hand. Defaults to true.
+    pub auto_migrate: bool,
+    /// Let a smaller `message_retention_secs` shrink an existing `CHANGEFEED`
+    /// window. Off by default: shrinking drops changes an offline agent may
+    /// not have consumed, so the longer window is kept instead.
+    pub allow_changefeed_shrink: bool,
+    /// Connection endpoint. `ws://`/`wss://`/`http(s)://` reach a server (a
+    /// local container outside production); `mem://` or `surrealkv://<path>`
+    /// run SurrealDB embedded in-process with no Docker at all.
     pub endpoint: String,
     /// Retention window (seconds) for the durable message log. Drives both the
     /// `message` table `CHANGEFEED` window (how far back a reconnecting agent can
     /// replay) and the periodic age-out sweep (`DELETE message WHERE created <
     /// now - retention`). Defaults to 24h. An agent offline longer than this
     /// loses the messages sent while it was gone.
-    #[serde(default = "Sdb::default_message_retention_secs")]
     pub message_retention_secs: u64,
+    /// How many times to try reaching `endpoint` before giving up. Only an
+    /// unreachable endpoint is retried; bad credentials or a namespace/schema
+    /// error fail on the first attempt. Defaults to 5.
+    pub connect_attempts: u32,
+    /// Initial delay (milliseconds) between connection attempts, doubling up
+    /// to 5s. Defaults to 200.
+    pub connect_backoff_ms: u64,
+    /// How the local container runs the server (`[sdb.container]`).
+    pub container: Container,
+    /// How long to wait for a started server to become ready
+    /// (`[sdb.health]`).
+    pub health: Health,
+}
+
+/// Readiness checks of a freshly started server: each attempt is an HTTP
+/// `/health` check followed by a signed-in query over `sdb.endpoint` (see
+/// `SurrealDBWrapper::probe`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Health {
+    /// Attempts before startup fails with `Error::HealthCheck`. Defaults
+    /// to 25.
+    pub attempts: u32,
+    /// Delay (milliseconds) between attempts. Defaults to 1000.
+    pub interval_ms: u64,
+    /// Time (milliseconds) one attempt may take. Defaults to 1000.
+    pub probe_timeout_ms: u64,
+    /// Overall limit (seconds) before startup fails with
+    /// `Error::StartupTimeout`. Defaults to 30.
+    pub startup_timeout_secs: u64,
 }
 
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            attempts: 25,
+            interval_ms: 1000,
+            probe_timeout_ms: 1000,
+            startup_timeout_secs: 30,
+        }
+    }
+}
+
+/// Server and runtime options of the local container. Ignored for embedded,
+/// production and attached databases.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Container {
+    /// `surreal start --log` level. Defaults to `info`.
+    pub log_level: String,
+    /// Extra `surreal start` arguments, e.g. to mirror production flags.
+    pub args: Vec<String>,
+    /// Environment variables set in the container.
+    pub env: BTreeMap<String, String>,
+    /// Memory limit in MiB; unlimited when unset.
+    pub memory_mb: Option<u64>,
+    /// CPU limit in cores (e.g. `1.5`); unlimited when unset.
+    pub cpus: Option<f64>,
+    /// Labels put on the container.
+    pub labels: BTreeMap<String, String>,
+    /// Forward the container's stdout/stderr into tracing (target
+    /// `sdb_container`) until it stops. Off by default.
+    pub stream_logs: bool,
+    /// Container log lines attached to a failed startup's error. Defaults to
+    /// 50; `0` attaches none.
+    pub failure_log_lines: usize,
+}
+
+impl Container {
+    /// `memory_mb` in bytes, as Docker takes it; `None` when unset or too
+    /// large for Docker's signed 64-bit limit.
+    pub(crate) fn memory_bytes(&self) -> Option<i64> {
+        self.memory_mb?
+            .checked_mul(1024 * 1024)
+            .and_then(|bytes| i64::try_from(bytes).ok())
+    }
+}
+
+impl Default for Container {
+    fn default() -> Self {
+        Self {
+            log_level: "info".to_string(),
+            args: Vec::new(),
+            env: BTreeMap::new(),
+            memory_mb: None,
+            cpus: None,
+            labels: BTreeMap::new(),
+            stream_logs: false,
+            failure_log_lines: 50,
+        }
+    }
+}
+
+/// Authentication mode for `sdb.auth`, selected by its `mode` key
+/// (`SDB__AUTH__MODE` in the environment).
+///
+/// Namespace and database users are scoped to `sdb.namespace` and
+/// `sdb.database`; so is a record access method.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum Auth {
+    /// Root user `sdb.username`/`sdb.password`.
+    #[default]
+    Root,
+    /// A `DEFINE USER ... ON NAMESPACE` user.
+    Namespace { username: String, password: String },
+    /// A `DEFINE USER ... ON DATABASE` user.
+    Database { username: String, password: String },
+    /// A `DEFINE ACCESS ... TYPE RECORD` method. `params` are passed to its
+    /// `SIGNIN` (and `SIGNUP`) clause. With `signup`, a signin whose clause
+    /// finds no record is followed by a signup, so the record user is created
+    /// on first use; any other signin failure is [`Error::Auth`].
+    Record {
+        access: String,
+        #[serde(default)]
+        params: BTreeMap<String, String>,
+        #[serde(default)]
+        signup: bool,
+    },
+    /// A pre-issued JWT, e.g. from an external identity provider behind a
+    /// `DEFINE ACCESS ... TYPE JWT` method. Replace it before it expires with
+    /// `Client::authenticate`.
+    Token { token: String },
+}
+
+impl Auth {
+    /// `true` for root, namespace and database users — the identities that
+    /// can define the schema and run table-wide maintenance. Record and token
+    /// sessions rely on a system user having done both.
+    pub fn is_system_user(&self) -> bool {
+        matches!(
+            self,
+            Self::Root | Self::Namespace { .. } | Self::Database { .. }
+        )
+    }
+}
+
+/// Endpoint schemes served by an in-process (embedded) SurrealDB engine rather
+/// than a server reached over the network.
+const EMBEDDED_SCHEMES: [&str; 3] = ["mem://", "surrealkv://", "rocksdb://"];
+
 impl Sdb {
-    fn default_message_retention_secs() -> u64 {
-        86_400 // 24h
+    /// Scheme, host and port of a server `endpoint` (`ws`, `wss`, `http` or
+    /// `https`, with the scheme's default port if none is given); `None` for
+    /// anything else.
+    fn server_address(&self) -> Option<(&str, &str, u16)> {
+        let (scheme, rest) = self.endpoint.split_once("://")?;
+        let default_port = match scheme {
+            "ws" | "http" => 80,
+            "wss" | "https" => 443,
+            _ => return None,
+        };
+        let authority = rest.split('/').next().unwrap_or_default();
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (host, port.parse().ok()?),
+            _ => (authority, default_port),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        (!host.is_empty()).then_some((scheme, host, port))
+    }
+
+    /// `true` when `endpoint` selects an embedded engine (`mem://`,
+    /// `surrealkv://<path>`, `rocksdb://<path>`). Embedded mode needs no Docker
+    /// container and no root signin — the process owns the datastore.
+    pub fn is_embedded(&self) -> bool {
+        EMBEDDED_SCHEMES
+            .iter()
+            .any(|scheme| self.endpoint.starts_with(scheme))
+    }
+}
+
+impl Default for Sdb {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 8000,
+            image: "surrealdb/surrealdb".to_string(),
+            tag: "v3.2.4".to_string(),
+            container_name: "sdb_container".to_string(),
+            namespace: "main".to_string(),
+            database: "main".to_string(),
+            username: "root".to_string(),
+            password: "root".to_string(),
+            auth: Auth::default(),
+            auth_refresh_secs: None,
+            secured: false,
+            auto_migrate: true,
+            allow_changefeed_shrink: false,
+            endpoint: "ws://127.0.0.1:8000".to_string(),
+            message_retention_secs: 86_400, // 24h
+            connect_attempts: 5,
+            connect_backoff_ms: 200,
+            container: Container::default(),
+            health: Health::default(),
+        }
     }
 }
 
+/// Every setting has a default (those of `config/default.toml`), so a config
+/// file only needs the values it changes — or can be left out entirely.
 #[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
 pub struct Settings {
     pub environment: String,
     pub logger: Logger,
@@ -51,15 +389,386 @@ pub struct Settings {
     pub sdb: Sdb,
 }
 
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            environment: "development".to_string(),
+            logger: Logger::default(),
+            docker: Docker::default(),
+            sdb: Sdb::default(),
+        }
+    }
+}
+
 impl Settings {
-    pub fn new() -> Result<Self, ConfigError> {
+    /// Load `config/default.toml` and `config/$RUN_MODE.toml` (both optional,
+    /// relative to the working directory; `RUN_MODE` defaults to
+    /// `development`), then `SECTION__KEY` environment overrides, over the
+    /// built-in defaults; fill an unset `docker.host` from `DOCKER_HOST`, and
+    /// [`validate`](Self::validate) the result.
+    pub fn new() -> Result<Self> {
         let run_mode = env::var("RUN_MODE").unwrap_or_else(|_| "development".into());
 
-        let builder = Config::builder()
-            .add_source(File::with_name("config/default"))
-            .add_source(File::with_name(&format!("config/{run_mode}")).required(false))
-            .add_source(Environment::default().separator("__"));
+        Self::load(
+            Config::builder()
+                .add_source(File::with_name("config/default").required(false))
+                .add_source(File::with_name(&format!("config/{run_mode}")).required(false)),
+        )
+    }
+
+    /// Like [`Settings::new`], but from the one file at `path` (which must
+    /// exist) instead of the `config/` directory. Environment overrides still
+    /// apply.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        Self::load(Config::builder().add_source(File::from(path.as_ref())))
+    }
+
+    /// Start from the built-in defaults and set values in code, without any
+    /// config file or environment (`DOCKER_HOST` included).
+    pub fn builder() -> SettingsBuilder {
+        SettingsBuilder::default()
+    }
+
+    /// The settings behind [`SETTINGS`], or why they failed to load.
+    pub fn global() -> Result<&'static Self> {
+        LOADED.as_ref().map_err(|reason| Error::Config {
+            reason: reason.clone(),
+        })
+    }
+
+    fn load(builder: ConfigBuilder<DefaultState>) -> Result<Self> {
+        let mut settings: Self = builder
+            .add_source(Environment::default().separator("__"))
+            .build()
+            .and_then(Config::try_deserialize)
+            .map_err(|e| Error::Config {
+                reason: e.to_string(),
+            })?;
+        if settings.docker.host.is_none() {
+            settings.docker.host = env::var("DOCKER_HOST").ok();
+        }
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Reject inconsistent settings with [`Error::Config`]:
+    ///
+    /// - `environment` other than `development`, `test` or `production`;
+    /// - empty `sdb.namespace` or `sdb.database`;
+    /// - zero `sdb.message_retention_secs` or `sdb.auth_refresh_secs`;
+    /// - a zero or negative container memory or CPU limit, or a memory limit
+    ///   too large to express in bytes;
+    /// - `docker.attach` together with `docker.dynamic_port`;
+    /// - an `sdb.endpoint` that is neither embedded nor `ws`/`wss`/`http`/
+    ///   `https`;
+    /// - outside production, where the endpoint reaches the local container,
+    ///   an endpoint whose host or port differs from `sdb.host`/`sdb.port`
+    ///   (the host may also be a remote Docker daemon's, the port anything
+    ///   under `docker.dynamic_port`). Loopback names are interchangeable:
+    ///   `localhost`, `127.0.0.1` and `::1` all match each other.
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |reason: String| Err(Error::Config { reason });
+        let sdb = &self.sdb;
+
+        if !ENVIRONMENTS.contains(&self.environment.as_str()) {
+            return invalid(format!(
+                "unknown environment '{}' (expected one of {})",
+                self.environment,
+                ENVIRONMENTS.join(", ")
+            ));
+        }
+        if sdb.namespace.is_empty() || sdb.database.is_empty() {
+            return invalid("sdb.namespace and sdb.database must not be empty".to_string());
+        }
+        if sdb.message_retention_secs == 0 {
+            return invalid("sdb.message_retention_secs must be greater than zero".to_string());
+        }
+        if sdb.auth_refresh_secs == Some(0) {
+            return invalid("sdb.auth_refresh_secs must be greater than zero".to_string());
+        }
+        if sdb.container.memory_mb == Some(0) || sdb.container.cpus.is_some_and(|c| c <= 0.0) {
+            return invalid(
+                "sdb.container.memory_mb and sdb.container.cpus must be greater than zero"
+                    .to_string(),
+            );
+        }
+        if sdb.container.memory_mb.is_some() && sdb.container.memory_bytes().is_none() {
+            return invalid(format!(
+                "sdb.container.memory_mb {} MiB does not fit Docker's memory limit",
+                sdb.container.memory_mb.unwrap_or_default()
+            ));
+        }
+        if sdb.health.attempts == 0
+            || sdb.health.probe_timeout_ms == 0
+            || sdb.health.startup_timeout_secs == 0
+        {
+            return invalid(
+                "sdb.health.attempts, probe_timeout_ms and startup_timeout_secs \
+                 must be greater than zero"
+                    .to_string(),
+            );
+        }
+        if self.docker.attach && self.docker.dynamic_port {
+            return invalid(
+                "docker.attach and docker.dynamic_port cannot be combined: attach looks \
+                 for sdb.host:sdb.port and sdb.container_name, which a dynamic port and \
+                 per-run name never match"
+                    .to_string(),
+            );
+        }
+        if sdb.is_embedded() {
+            return Ok(());
+        }
+        let Some((_, host, port)) = sdb.server_address() else {
+            return invalid(format!(
+                "sdb.endpoint '{}' is neither embedded nor ws://, wss://, http:// or https://",
+                sdb.endpoint
+            ));
+        };
+        let host_differs =
+            !same_host(host, &sdb.host) && self.docker.remote_host().as_deref() != Some(host);
+        let port_differs = port != sdb.port && !self.docker.dynamic_port;
+        if self.environment != "production" && (host_differs || port_differs) {
+            return invalid(format!(
+                "sdb.endpoint '{}' does not match the local container at sdb.host:sdb.port ({}:{})",
+                sdb.endpoint, sdb.host, sdb.port
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Whether `a` and `b` name the same host, treating every loopback name
+/// (`localhost`, `127.0.0.0/8`, `::1`) as one.
+fn same_host(a: &str, b: &str) -> bool {
+    a.eq_ignore_ascii_case(b) || (is_loopback(a) && is_loopback(b))
+}
+
+fn is_loopback(host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Programmatic [`Settings`], from [`Settings::builder`]. Starts at the
+/// built-in defaults; [`build`](Self::build) validates.
+///
+/// `host` and `port` move the default endpoint with them until
+/// [`endpoint`](Self::endpoint) is set explicitly.
+#[derive(Debug, Clone, Default)]
+pub struct SettingsBuilder {
+    settings: Settings,
+    endpoint_set: bool,
+}
+
+impl SettingsBuilder {
+    pub fn environment(mut self, environment: impl Into<String>) -> Self {
+        self.settings.environment = environment.into();
+        self
+    }
+
+    pub fn log_level(mut self, level: impl Into<String>) -> Self {
+        self.settings.logger.level = level.into();
+        self
+    }
+
+    pub fn docker_platform(mut self, platform: impl Into<String>) -> Self {
+        self.settings.docker.platform = Some(platform.into());
+        self
+    }
+
+    /// Daemon to manage the container through (see [`Docker::host`]).
+    pub fn docker_host(mut self, host: impl Into<String>) -> Self {
+        self.settings.docker.host = Some(host.into());
+        self
+    }
+
+    pub fn endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.settings.sdb.endpoint = endpoint.into();
+        self.endpoint_set = true;
+        self
+    }
+
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.settings.sdb.host = host.into();
+        self.sync_endpoint();
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.settings.sdb.port = port;
+        self.sync_endpoint();
+        self
+    }
+
+    pub fn namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.settings.sdb.namespace = namespace.into();
+        self
+    }
+
+    pub fn database(mut self, database: impl Into<String>) -> Self {
+        self.settings.sdb.database = database.into();
+        self
+    }
+
+    /// Root credentials (see [`Sdb::username`]).
+    pub fn credentials(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.settings.sdb.username = username.into();
+        self.settings.sdb.password = password.into();
+        self
+    }
+
+    pub fn auth(mut self, auth: Auth) -> Self {
+        self.settings.sdb.auth = auth;
+        self
+    }
+
+    pub fn message_retention_secs(mut self, secs: u64) -> Self {
+        self.settings.sdb.message_retention_secs = secs;
+        self
+    }
+
+    /// Set any other `sdb` value.
+    pub fn sdb(mut self, f: impl FnOnce(&mut Sdb)) -> Self {
+        f(&mut self.settings.sdb);
+        self
+    }
+
+    pub fn build(self) -> Result<Settings> {
+        self.settings.validate()?;
+        Ok(self.settings)
+    }
+
+    fn sync_endpoint(&mut self) {
+        if !self.endpoint_set {
+            let sdb = &mut self.settings.sdb;
+            sdb.endpoint = format!("ws://{}:{}", sdb.host, sdb.port);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_error(settings: Settings) -> String {
+        match settings.validate() {
+            Err(Error::Config { reason }) => reason,
+            other => panic!("expected Error::Config, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        Settings::default().validate().expect("defaults validate");
+        Settings::builder()
+            .build()
+            .expect("builder defaults validate");
+    }
+
+    #[test]
+    fn builder_moves_the_default_endpoint() {
+        let settings = Settings::builder()
+            .host("10.0.0.5")
+            .port(9000)
+            .build()
+            .expect("valid");
+        assert_eq!(settings.sdb.endpoint, "ws://10.0.0.5:9000");
+
+        let settings = Settings::builder()
+            .endpoint("mem://")
+            .port(9000)
+            .build()
+            .expect("valid");
+        assert_eq!(settings.sdb.endpoint, "mem://");
+    }
+
+    #[test]
+    fn rejects_inconsistent_settings() {
+        let mut settings = Settings::default();
+        settings.environment = "staging".to_string();
+        assert!(config_error(settings).contains("unknown environment"));
+
+        let mut settings = Settings::default();
+        settings.sdb.message_retention_secs = 0;
+        assert!(config_error(settings).contains("message_retention_secs"));
+
+        let mut settings = Settings::default();
+        settings.sdb.container.memory_mb = Some(u64::MAX / 1024);
+        assert!(config_error(settings).contains("memory_mb"));
+
+        let mut settings = Settings::default();
+        settings.sdb.container.memory_mb = Some(512);
+        assert_eq!(
+            settings.sdb.container.memory_bytes(),
+            Some(512 * 1024 * 1024)
+        );
+        settings.validate().expect("a 512 MiB limit");
+
+        let mut settings = Settings::default();
+        settings.sdb.health.attempts = 0;
+        assert!(config_error(settings).contains("sdb.health"));
+
+        let mut settings = Settings::default();
+        settings.sdb.endpoint = "ws://127.0.0.1:9000".to_string();
+        assert!(config_error(settings).contains("does not match"));
+
+        let mut settings = Settings::default();
+        settings.sdb.endpoint = "ftp://127.0.0.1:8000".to_string();
+        assert!(config_error(settings).contains("neither embedded"));
+
+        let mut settings = Settings::default();
+        settings.docker.attach = true;
+        settings.docker.dynamic_port = true;
+        assert!(config_error(settings).contains("docker.attach"));
+    }
+
+    #[test]
+    fn loopback_names_match() {
+        for endpoint in [
+            "ws://localhost:8000",
+            "ws://[::1]:8000",
+            "ws://127.0.0.1:8000",
+        ] {
+            let mut settings = Settings::default();
+            settings.sdb.host = "127.0.0.1".to_string();
+            settings.sdb.endpoint = endpoint.to_string();
+            settings.validate().expect(endpoint);
+        }
+
+        let mut settings = Settings::default();
+        settings.sdb.host = "localhost".to_string();
+        settings.sdb.endpoint = "ws://10.0.0.5:8000".to_string();
+        assert!(config_error(settings).contains("does not match"));
+    }
+
+    #[test]
+    fn remote_docker_host() {
+        let mut docker = Docker {
+            host: Some("tcp://10.0.0.2:2376".to_string()),
+            ..Docker::default()
+        };
+        assert_eq!(docker.remote_host().as_deref(), Some("10.0.0.2"));
+        docker.host = Some("unix:///run/user/1000/podman/podman.sock".to_string());
+        assert_eq!(docker.remote_host(), None);
+
+        let mut settings = Settings::default();
+        settings.docker.host = Some("tcp://10.0.0.2:2375".to_string());
+        settings.sdb.endpoint = "ws://10.0.0.2:8000".to_string();
+        settings.validate().expect("endpoint on the daemon's host");
+    }
 
-        builder.build()?.try_deserialize()
+    #[test]
+    fn production_endpoint_may_differ_from_host() {
+        let mut settings = Settings::default();
+        settings.environment = "production".to_string();
+        settings.sdb.endpoint = "wss://db.example.com/rpc".to_string();
+        settings.validate().expect("remote endpoint in production");
+        assert_eq!(
+            settings.sdb.server_address(),
+            Some(("wss", "db.example.com", 443))
+        );
     }
 }
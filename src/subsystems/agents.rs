@@ -1,98 +1,178 @@
-use std::{collections::HashMap, marker::PhantomData, sync::Arc};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, PoisonError};
 
-use futures::StreamExt;
 use kanal::{AsyncReceiver, AsyncSender};
 use serde::{Deserialize, Serialize};
+use surrealdb::Surreal;
 use surrealdb::engine::any;
-use surrealdb::{Notification, Surreal};
-use surrealdb_types::{Datetime, RecordId, SurrealValue, Value};
-use tokio::sync::{RwLock, oneshot};
-use tokio::time::{Duration, sleep, timeout};
+use surrealdb_types::{Datetime, RecordId, RecordIdKey, SurrealValue, ToSql, Value};
+use tokio::sync::{Mutex as AsyncMutex, RwLock, mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant, MissedTickBehavior, interval, sleep, timeout};
 use tokio_util::{sync::CancellationToken, task::TaskTracker};
 use tracing::Instrument;
 
+use crate::ack::{AckEvent, AckMode, AckTracker, Acker, Redelivery};
+use crate::client::Client;
+use crate::dead_letter;
 use crate::error::{Error, Result};
 use crate::message::{MESSAGE_TABLE, Message};
-use crate::settings::SETTINGS;
-use crate::subsystems::sdb;
+use crate::subsystems::changefeed::{self, Batch, ChangefeedReader, ReaderEvent, versionstamp_of};
+use crate::subsystems::dispatch::{AgentHandler, dispatch_loop};
+use crate::topic::{self, PUBLICATION_TABLE, SubscribeFrom, TOPIC_TABLE};
 
 pub const AGENT_TABLE: &str = "agent";
 
 /// Maximum time `Coalition::new` waits for each `listen_loop` to signal that
-/// its LIVE query is registered before giving up. Guards against a DB that
-/// stalls during LIVE registration (so the sender is neither sent nor dropped).
+/// it is registered with the coalition's changefeed reader before giving up.
+/// Guards against a DB that stalls while the loop loads or seeds its cursor
+/// (so the sender is neither sent nor dropped).
 const READY_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// Capacity of the shared MPMC delivery bus connecting agent listen loops to
 /// downstream consumers via [`Coalition::inbox`].
 const INBOX_CAPACITY: usize = 256;
 
+/// Default capacity of each agent's own inbox ([`Coalition::inbox_for`]).
+const AGENT_INBOX_CAPACITY: usize = 64;
+
 /// Table holding each agent's durable-log high-water-mark cursor (`cursor:<agent>`).
 pub const CURSOR_TABLE: &str = "cursor";
 
-/// Max changesets pulled per `SHOW CHANGES` page during catch-up.
-const CATCHUP_BATCH: usize = 1000;
-
-/// Reconnect backoff bounds for the LIVE wake-up subscription.
-const RECONNECT_BACKOFF_START: Duration = Duration::from_millis(200);
-const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(30);
+/// Bounds on how often a listen loop under [`AckMode::Manual`] checks for
+/// deliveries whose visibility timeout has expired (half the timeout, clamped).
+const REDELIVERY_TICK_MIN: Duration = Duration::from_millis(10);
+const REDELIVERY_TICK_MAX: Duration = Duration::from_secs(1);
 
 /// An inbound message delivered to an agent's listen loop, forwarded onto the
 /// coalition's shared kanal bus. `recipient` is the agent that received it;
 /// `message.r#in` is the sender.
+///
+/// A publication received through a topic subscription has `topic` set, and
+/// its `message.out` is the topic record rather than the recipient.
+///
+/// Under [`AckMode::Manual`] the consumer must [`ack`](Delivery::ack) (or
+/// [`nack`](Delivery::nack)) each delivery; `attempt` counts redeliveries
+/// within this process, starting at 1.
 #[derive(Debug)]
 pub struct Delivery<T: SurrealValue> {
     pub recipient: String,
+    /// The topic this publication was received through; `None` for
+    /// point-to-point messages.
+    pub topic: Option<String>,
     pub message: Message<T>,
+    pub attempt: u32,
+    acker: Acker,
+}
+
+/// Where a listen loop pushes its deliveries: the shared bus, or — once a
+/// consumer has claimed it through [`Coalition::inbox_for`] — the agent's own
+/// inbox, so a slow per-agent consumer only backpressures that agent.
+struct DeliverySink<T: SurrealValue> {
+    shared: AsyncSender<Delivery<T>>,
+    own: AsyncSender<Delivery<T>>,
+    claimed: Arc<AtomicBool>,
+}
+
+impl<T: SurrealValue> DeliverySink<T> {
+    /// Push one delivery, applying the target channel's backpressure. A closed
+    /// channel is logged, not fatal — the consumer side has gone away.
+    async fn send(&self, agent: &str, delivery: Delivery<T>) {
+        let sent = if self.claimed.load(Ordering::Acquire) {
+            self.own.send(delivery).await
+        } else {
+            self.shared.send(delivery).await
+        };
+        if let Err(e) = sent {
+            tracing::debug!("inbox bus closed for {agent}: {e}");
+        }
+    }
+}
+
+impl<T: SurrealValue> Delivery<T> {
+    /// Acknowledge this delivery so the agent's cursor may advance past it.
+    /// No-op under [`AckMode::Auto`].
+    pub fn ack(&self) {
+        self.acker.ack();
+    }
+
+    /// Reject this delivery so it is redelivered immediately. No-op under
+    /// [`AckMode::Auto`].
+    pub fn nack(&self) {
+        self.acker.nack();
+    }
+
+    /// A detached ack handle, for consumers that move `message` out first.
+    pub fn acker(&self) -> Acker {
+        self.acker.clone()
+    }
 }
 
 // ============================================================================
 // Agent
 // ============================================================================
 
-#[derive(Debug, Serialize, Deserialize, Clone, SurrealValue)]
+/// An agent handle: its `agent` record plus the [`Client`] it talks through.
+#[derive(Debug, Clone)]
 pub struct Agent {
     pub id: RecordId,
     pub name: String,
+    client: Client,
+}
+
+/// An `agent` row as stored.
+#[derive(Debug, Serialize, Deserialize, SurrealValue)]
+struct AgentRecord {
+    id: RecordId,
+    name: String,
     created: Datetime,
 }
 
 impl Agent {
-    /// Create (or reuse) an agent record in the `agent` table.
+    /// Create (or reuse) an agent record in the `agent` table, on the
+    /// process-wide [`Client::global`].
     ///
     /// Validates `name` first: it becomes a SurrealDB record-id key *and* is
-    /// bound into LIVE queries, so it is restricted to a safe character set
+    /// bound into queries, so it is restricted to a safe character set
     /// (non-empty, ASCII alphanumeric or underscore). Rejecting at the boundary
     /// keeps malformed names out of the system rather than relying on downstream
     /// escaping.
     pub async fn new(name: &str) -> Result<Self> {
+        Self::new_with_client(name, &Client::global().await?).await
+    }
+
+    /// Like [`Agent::new`] on an explicit `client`; every call on the returned
+    /// agent goes through it.
+    pub async fn new_with_client(name: &str, client: &Client) -> Result<Self> {
         if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
             return Err(Error::InvalidAgentName {
                 name: name.to_string(),
             });
         }
 
-        let db = sdb::SurrealDBWrapper::connection().await;
+        let db = client.db();
 
         // Reuse an existing agent record if present. Agents are now durable —
         // they are NOT deleted on shutdown (the message log persists, so the
         // agent identity must too), and `create` errors on a duplicate id. A
         // select-then-create makes coalition *restart* idempotent, which is what
         // lets a restarted agent resume its durable-log cursor.
-        if let Some(existing) = db
+        let existing: Option<AgentRecord> = db
             .select((AGENT_TABLE, name))
             .await
             .map_err(|source| Error::AgentCreate {
                 agent: name.to_string(),
                 source,
-            })?
-        {
-            return Ok(existing);
+            })?;
+        if let Some(existing) = existing {
+            return Ok(Self::from_record(existing, client));
         }
 
-        let agent: Agent = db
+        let record: AgentRecord = db
             .create((AGENT_TABLE, name))
-            .content(Agent {
+            .content(AgentRecord {
                 id: RecordId::new(AGENT_TABLE, name),
                 name: name.to_string(),
                 created: Datetime::default(),
@@ -110,7 +190,20 @@ impl Agent {
                 agent: name.to_string(),
             })?;
 
-        Ok(agent)
+        Ok(Self::from_record(record, client))
+    }
+
+    fn from_record(record: AgentRecord, client: &Client) -> Self {
+        Self {
+            id: record.id,
+            name: record.name,
+            client: client.clone(),
+        }
+    }
+
+    /// The client this agent talks through.
+    pub fn client(&self) -> &Client {
+        &self.client
     }
 
     /// Send a typed payload to another agent by creating a RELATE edge in the
@@ -123,14 +216,139 @@ impl Agent {
     where
         T: SurrealValue + Send + Sync + Unpin + 'static,
     {
-        let db = sdb::SurrealDBWrapper::connection().await;
+        self.relate(to, RecordId::new(AGENT_TABLE, to), payload, None, None)
+            .await
+    }
+
+    /// Send `payload` to `to` as a request and wait up to `timeout` for the
+    /// matching [`Agent::reply`].
+    ///
+    /// The edge is stamped with a fresh `correlation_id`. This agent's listen
+    /// loop routes the reply edge echoing it straight back to this future
+    /// instead of onto [`Coalition::inbox`], decoded as `Resp` (which need not
+    /// be the coalition's payload type).
+    ///
+    /// The requester must be a running coalition member: only its listen loop
+    /// routes replies, so a request from an [`Agent`] with no listen loop
+    /// (e.g. one from [`Agent::new_with_client`]) always ends in
+    /// [`Error::RequestTimeout`] — as does any request with no reply in
+    /// time. A reply arriving after the timeout is handled like any other
+    /// message: it goes to the inbox if it decodes as the coalition's `T`,
+    /// and is dead-lettered otherwise (see [`crate::dead_letter`]).
+    pub async fn request<Req, Resp>(
+        &self,
+        to: &str,
+        payload: Req,
+        timeout: Duration,
+    ) -> Result<Message<Resp>>
+    where
+        Req: SurrealValue + Send + Sync + Unpin + 'static,
+        Resp: SurrealValue,
+    {
+        let correlation_id = uuid::Uuid::new_v4().to_string();
+        let (reply_tx, reply_rx) = oneshot::channel();
+        // Register before sending so a fast reply can't beat the waiter.
+        let _waiter = ReplyWaiter::register(&self.name, &correlation_id, reply_tx);
+        self.relate(
+            to,
+            RecordId::new(AGENT_TABLE, to),
+            payload,
+            Some(correlation_id),
+            None,
+        )
+        .await?;
+
+        // The sender stays in the registry until a reply is routed or
+        // `_waiter` drops, so the channel can't close first; any non-reply
+        // outcome is the timeout.
+        let Ok(Ok(record)) = tokio::time::timeout(timeout, reply_rx).await else {
+            return Err(Error::RequestTimeout {
+                to: to.to_string(),
+                timeout,
+            });
+        };
+        Message::<Resp>::from_value(record).map_err(|e| Error::ReplyDecode {
+            from: to.to_string(),
+            reason: e.to_string(),
+        })
+    }
+
+    /// Answer a message received from [`Agent::request`]: sends `payload` back
+    /// to the request's sender with `reply_to` set to its `correlation_id`.
+    /// Returns [`Error::NotARequest`] for a plain (uncorrelated) message.
+    pub async fn reply<Req, Resp>(&self, request: &Message<Req>, payload: Resp) -> Result<()>
+    where
+        Req: SurrealValue,
+        Resp: SurrealValue + Send + Sync + Unpin + 'static,
+    {
+        let (Some(correlation_id), Some(to_id)) = (&request.correlation_id, &request.r#in) else {
+            return Err(Error::NotARequest);
+        };
+        self.relate(
+            &agent_name(to_id),
+            to_id.clone(),
+            payload,
+            None,
+            Some(correlation_id.clone()),
+        )
+        .await
+    }
+
+    /// Publish `payload` to `topic`: a single `agent->publication->topic` edge
+    /// that every subscriber of the topic receives (see
+    /// [`Coalition::subscribe`]), including ones that subscribe later within
+    /// the retention window. The topic is created on first use; publishing to
+    /// a topic nobody follows is not an error.
+    ///
+    /// Returns [`Error::InvalidTopicName`] for a name that is not a safe
+    /// record-id key.
+    pub async fn publish<T>(&self, topic: &str, payload: T) -> Result<()>
+    where
+        T: SurrealValue + Send + Sync + Unpin + 'static,
+    {
+        topic::validate(topic)?;
+        let db = self.client.db();
+        let publish_err = |source| Error::Publish {
+            topic: topic.to_string(),
+            source,
+        };
+
+        let query = "UPSERT $topic SET name = $name; \
+            RELATE $from->publication->$topic CONTENT { \
+                created: time::now(), payload: $payload };";
+        db.query(query)
+            .bind(("topic", RecordId::new(TOPIC_TABLE, topic)))
+            .bind(("name", topic.to_string()))
+            .bind(("from", self.id.clone()))
+            .bind(("payload", payload))
+            .await
+            .map_err(publish_err)?
+            .check()
+            .map_err(publish_err)?;
+        tracing::info!(topic, from = %self.name, "message published");
+        Ok(())
+    }
+
+    /// Create the `message` edge behind [`Agent::send`], [`Agent::request`] and
+    /// [`Agent::reply`]. `to` only labels errors; `to_id` is the recipient.
+    async fn relate<T>(
+        &self,
+        to: &str,
+        to_id: RecordId,
+        payload: T,
+        correlation_id: Option<String>,
+        reply_to: Option<String>,
+    ) -> Result<()>
+    where
+        T: SurrealValue + Send + Sync + Unpin + 'static,
+    {
+        let db = self.client.db();
         let from_id = self.id.clone();
-        let to_id = RecordId::new(AGENT_TABLE, to);
 
         // Reject sends to a recipient that has no `agent` record. Without this,
         // RELATE silently creates an edge whose `out` dangles at a non-existent
         // agent.
-        let recipient: Option<Agent> =
+        let recipient: Option<AgentRecord> =
             db.select(to_id.clone())
                 .await
                 .map_err(|source| Error::Send {
@@ -141,14 +359,22 @@ impl Agent {
             return Err(Error::UnknownRecipient { to: to.to_string() });
         }
 
-        let query =
-            "RELATE $from->message->$to CONTENT { created: time::now(), payload: $payload };";
+        // An unset correlation id binds as NONE, which leaves the field off the
+        // edge entirely.
+        let query = "RELATE $from->message->$to CONTENT { \
+            created: time::now(), payload: $payload, \
+            correlation_id: $correlation_id, reply_to: $reply_to };";
 
         db.query(query)
             .bind(("from", from_id))
             .bind(("to", to_id))
             .bind(("payload", payload))
+            .bind(("correlation_id", correlation_id))
+            .bind(("reply_to", reply_to))
             .await
+            // A RELATE denied by the secured schema's PERMISSIONS is a
+            // statement error inside an `Ok` response.
+            .and_then(|r| r.check())
             .map_err(|source| Error::Send {
                 to: to.to_string(),
                 source,
@@ -170,12 +396,25 @@ impl Agent {
     /// (LIVE alone is at-most-once with no replay; see the `live-queries`
     /// skill, "Delivery guarantees".)
     ///
-    /// `ready_tx` fires after the first successful subscribe + backlog drain, so
-    /// `Agent::send` calls issued after `Coalition::new` returns cannot race the
-    /// subscription. A startup-phase failure (before `ready_tx` fires) returns
-    /// `Err`, dropping the sender so `Coalition::new` surfaces it; failures after
-    /// readiness reconnect with capped exponential backoff. Messages are NEVER
-    /// deleted here — the log is aged out by the coalition's retention sweep.
+    /// Both tiers are the coalition's, not this loop's: one wake-up and one
+    /// [`ChangefeedReader`] scan serve every local agent (see
+    /// [`crate::subsystems::changefeed`]). This loop registers at its cursor
+    /// and delivers the batches demultiplexed to it.
+    ///
+    /// `ready_tx` fires once the agent is registered with the reader, whose
+    /// wake-up is already live, so `Agent::send` calls issued after
+    /// `Coalition::new` returns cannot be missed. The backlog is delivered
+    /// afterwards, so a backlog larger than the bus cannot stall
+    /// `Coalition::new`. A startup failure (loading or seeding the cursor)
+    /// returns `Err`, dropping the sender so `Coalition::new` surfaces it.
+    /// Messages are NEVER deleted here — the log is aged out by the
+    /// coalition's retention sweep.
+    ///
+    /// Under [`AckMode::Manual`] the read position and the persisted cursor
+    /// diverge: catch-up reads ahead, while `cursor:<agent>` — and each topic
+    /// subscription's cursor — only advances to the lowest unacknowledged
+    /// versionstamp (see [`crate::ack`]). Expired or nacked deliveries are
+    /// re-sent from the same loop.
     ///
     /// Library-side lifecycle primitive. No `SubsystemHandle`, no signal
     /// handling. See `rust-practical:async-lifecycle` skill.
@@ -183,126 +422,123 @@ impl Agent {
         self,
         token: CancellationToken,
         ready_tx: oneshot::Sender<()>,
-        inbox_tx: AsyncSender<Delivery<T>>,
+        sink: DeliverySink<T>,
+        ack_mode: AckMode,
+        max_attempts: Option<u32>,
+        reader: Arc<ChangefeedReader>,
     ) -> Result<()>
     where
         T: SurrealValue + Send + Sync + Unpin + 'static,
     {
         tracing::info!("listen_loop starting for {}", self.name);
-        let db = sdb::SurrealDBWrapper::connection().await;
+        let db = self.client.db();
         let owner = RecordId::new(AGENT_TABLE, self.name.clone());
 
         // Cursor: resume from the persisted high-water mark, or on first run
         // snapshot the current latest versionstamp so a brand-new agent starts
         // "from now" instead of replaying pre-existing backlog.
-        let mut cursor = match load_cursor(db, &self.name).await? {
+        let cursor = match load_cursor(&db, &self.name).await? {
             Some(c) => c,
             None => {
-                let start = latest_versionstamp(db, &self.name).await? + 1;
-                save_cursor(db, &self.name, start).await?;
+                let start =
+                    reader
+                        .next_versionstamp(&db, MESSAGE_TABLE)
+                        .await
+                        .map_err(|source| Error::CatchUp {
+                            agent: self.name.clone(),
+                            source,
+                        })?;
+                save_cursor(&db, &self.name, start).await?;
                 start
             }
         };
 
-        let mut ready_tx = Some(ready_tx);
-        let mut backoff = RECONNECT_BACKOFF_START;
+        // Manual ack: deliveries are tracked until acked, and the tracker (not
+        // the read position) decides how far the persisted cursor may move.
+        // Under Auto there is no tracker and the ack/redelivery branches below
+        // are off.
+        let (ack_tx, mut ack_rx) = mpsc::unbounded_channel();
+        let (mut acks, tick) = match ack_mode {
+            AckMode::Auto => (None, REDELIVERY_TICK_MAX),
+            AckMode::Manual { visibility_timeout } => (
+                Some(AckTracker::new(
+                    visibility_timeout,
+                    max_attempts,
+                    ack_tx,
+                    cursor,
+                )),
+                (visibility_timeout / 2).clamp(REDELIVERY_TICK_MIN, REDELIVERY_TICK_MAX),
+            ),
+        };
+        let mut redelivery = interval(tick);
+        redelivery.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        // Register from the read position (and each topic subscription from
+        // its own cursor); this also schedules the scan that replays our
+        // backlog. Dropping `batches` on exit deregisters.
+        let topics = topic::load(&db, &self.name).await?;
+        let mut read = ReadPositions {
+            messages: cursor,
+            topics: topics
+                .iter()
+                .map(|s| (s.topic.clone(), s.versionstamp))
+                .collect(),
+        };
+        let mut batches = reader.register(&self.name, owner, cursor, topics).await;
+        let _ = ready_tx.send(());
 
         loop {
-            // (Re)subscribe the LIVE wake-up. The payload is ignored (typed as
-            // `Value` so it can never fail to deserialize) — it only signals
-            // "something changed"; delivery happens via catch_up. The owner is
-            // bound as a param so the agent name can't alter the query.
-            let query = "LIVE SELECT id FROM message WHERE out = $owner";
-            let subscribe = async {
-                let mut response =
-                    db.query(query)
-                        .bind(("owner", owner.clone()))
-                        .await
-                        .map_err(|source| Error::LiveQuery {
-                            agent: self.name.clone(),
-                            source,
-                        })?;
-                response
-                    .stream::<Notification<Value>>(0)
-                    .map_err(|source| Error::Stream {
-                        agent: self.name.clone(),
-                        source,
-                    })
-            };
-            let mut stream = match subscribe.await {
-                Ok(s) => s,
-                Err(e) if ready_tx.is_some() => return Err(e), // startup failure
-                Err(e) => {
-                    tracing::error!("resubscribe failed for {}: {e}", self.name);
-                    if cancel_or_sleep(&token, backoff).await {
-                        return Ok(());
+            tokio::select! {
+                // `biased`: cancellation takes strict priority so a hot
+                // stream can't starve shutdown.
+                biased;
+                _ = token.cancelled() => {
+                    tracing::info!("listen_loop for {} received shutdown", self.name);
+                    // Commit acks that landed before the cancel so a clean
+                    // shutdown doesn't redeliver already-handled messages.
+                    if let Some(tracker) = acks.as_mut() {
+                        let now = Instant::now();
+                        while let Ok(event) = ack_rx.try_recv() {
+                            tracker.apply(event, now);
+                        }
+                        if let Err(e) = commit_cursors(&self.client.db(), &self.name, &read, &mut acks).await {
+                            tracing::warn!("final cursor commit failed for {}: {e}", self.name);
+                        }
                     }
-                    backoff = next_backoff(backoff);
-                    continue;
-                }
-            };
-
-            // Drain the durable log up to the live edge. Anything published
-            // during the drain is buffered by the live stream and handled by the
-            // next catch_up in the inner loop.
-            if let Err(e) =
-                catch_up::<T>(db, &owner, &self.name, &mut cursor, &inbox_tx).await
-            {
-                if ready_tx.is_some() {
-                    return Err(e); // startup failure
-                }
-                tracing::error!("catch_up failed for {}: {e}", self.name);
-                drop(stream);
-                if cancel_or_sleep(&token, backoff).await {
                     return Ok(());
                 }
-                backoff = next_backoff(backoff);
-                continue;
-            }
-
-            // First successful subscribe + drain: signal readiness once.
-            if let Some(tx) = ready_tx.take() {
-                let _ = tx.send(());
-            }
-            backoff = RECONNECT_BACKOFF_START;
-
-            // Inner loop: each wake triggers a catch_up; stream end/error
-            // breaks out to reconnect.
-            loop {
-                tokio::select! {
-                    // `biased`: cancellation takes strict priority so a hot
-                    // stream can't starve shutdown.
-                    biased;
-                    _ = token.cancelled() => {
-                        tracing::info!("listen_loop for {} received shutdown", self.name);
-                        return Ok(());
+                Some(event) = ack_rx.recv(), if acks.is_some() => {
+                    if let Err(e) =
+                        on_ack_event::<T>(&self.client.db(), &self.name, event, &mut ack_rx, &read, &mut acks, &sink).await
+                    {
+                        // The next commit retries from the same tracker state.
+                        tracing::error!("cursor commit failed for {}: {e}", self.name);
                     }
-                    maybe = stream.next() => match maybe {
-                        Some(Ok(_wake)) => {
-                            if let Err(e) =
-                                catch_up::<T>(db, &owner, &self.name, &mut cursor, &inbox_tx).await
-                            {
-                                tracing::error!("catch_up failed for {}: {e}", self.name);
-                                break;
-                            }
-                        }
-                        Some(Err(error)) => {
-                            tracing::error!("live wake stream error for {}: {error}", self.name);
-                            break;
+                }
+                _ = redelivery.tick(), if acks.is_some() => {
+                    redeliver_expired::<T>(&self.client.db(), &self.name, &mut acks, &sink).await;
+                }
+                maybe = batches.recv() => match maybe {
+                    Some(ReaderEvent::Batch(batch)) => {
+                        if let Err(e) =
+                            deliver_batch::<T>(&self.client.db(), &self.name, batch, &mut read, &mut acks, &sink).await
+                        {
+                            // Already delivered; the next commit persists past it.
+                            tracing::error!("cursor commit failed for {}: {e}", self.name);
                         }
-                        None => {
-                            tracing::info!("live wake stream ended for {}, reconnecting", self.name);
-                            break;
+                    }
+                    Some(ReaderEvent::Unsubscribed(topic)) => {
+                        read.topics.remove(&topic);
+                        if let Some(tracker) = acks.as_mut() {
+                            tracker.forget(&topic);
                         }
                     }
+                    None => {
+                        tracing::info!("changefeed reader gone, stopping listen_loop for {}", self.name);
+                        return Ok(());
+                    }
                 }
             }
-
-            drop(stream);
-            if cancel_or_sleep(&token, backoff).await {
-                return Ok(());
-            }
-            backoff = next_backoff(backoff);
         }
     }
 }
@@ -311,6 +547,46 @@ impl Agent {
 // Coalition<T>
 // ============================================================================
 
+/// Tunables for [`Coalition::new_with_options`]. `Default` matches
+/// [`Coalition::new`].
+///
+/// Between the coalition's changefeed reader and each listen loop sits an
+/// unbounded queue of scanned batches, so one slow consumer never stalls the
+/// reader for the rest. Once an agent's inbox (`agent_inbox_capacity`) is
+/// full, that queue grows instead, by every change addressed to the agent,
+/// for as long as its consumer falls behind. The retention sweep does not
+/// trim it, so size inboxes (or handler concurrency) for the agent's peak
+/// rate; a consumer that stops for good should leave the coalition.
+#[derive(Debug, Clone)]
+pub struct CoalitionOptions {
+    /// Per-agent readiness-handshake bound (default [`READY_TIMEOUT`]).
+    pub ready_timeout: Duration,
+    /// Cursor-commit policy for every agent's listen loop.
+    pub ack_mode: AckMode,
+    /// Capacity of each agent's own inbox (default 64); see
+    /// [`Coalition::inbox_for`].
+    pub agent_inbox_capacity: usize,
+    /// Max deliveries one registered handler processes at once (default 1,
+    /// i.e. in order); see [`Coalition::register_handler`].
+    pub handler_concurrency: usize,
+    /// Under [`AckMode::Manual`], dead-letter a delivery still unacknowledged
+    /// after this many attempts instead of redelivering it (default `None`:
+    /// redeliver indefinitely); see [`crate::dead_letter`].
+    pub max_delivery_attempts: Option<u32>,
+}
+
+impl Default for CoalitionOptions {
+    fn default() -> Self {
+        Self {
+            ready_timeout: READY_TIMEOUT,
+            ack_mode: AckMode::default(),
+            agent_inbox_capacity: AGENT_INBOX_CAPACITY,
+            handler_concurrency: 1,
+            max_delivery_attempts: None,
+        }
+    }
+}
+
 /// A scoped group of agents all exchanging payloads of type `T`.
 ///
 /// Library-first lifecycle — exposes `cancellation_token()` so callers wire
@@ -318,19 +594,43 @@ impl Agent {
 /// cancel-close-drain three-step. See `rust-practical:async-lifecycle` skill.
 pub struct Coalition<T: SurrealValue + Send + Sync + Unpin + 'static> {
     agents: Arc<RwLock<HashMap<String, Agent>>>,
+    /// Per-agent child token + listen-loop handle, so one agent can leave
+    /// without touching the rest. Held across a whole join/leave, which
+    /// serialises membership changes.
+    members: AsyncMutex<HashMap<String, Member<T>>>,
     task_tracker: TaskTracker,
     cancellation_token: CancellationToken,
     inbox: AsyncReceiver<Delivery<T>>,
+    /// The coalition's own bus sender, cloned into agents that join later.
+    /// Taken on shutdown so the bus closes once the last agent drains.
+    inbox_tx: Mutex<Option<AsyncSender<Delivery<T>>>>,
+    /// One `SHOW CHANGES` reader shared by every listen loop.
+    reader: Arc<ChangefeedReader>,
+    client: Client,
+    options: CoalitionOptions,
     _payload: PhantomData<T>,
 }
 
+/// A running listen loop: its own child token, the handle to drain it, its
+/// own inbox (fed instead of the shared bus once `claimed`), and the dispatch
+/// loop of its registered handler, if any.
+struct Member<T: SurrealValue> {
+    token: CancellationToken,
+    handle: JoinHandle<Result<()>>,
+    inbox: AsyncReceiver<Delivery<T>>,
+    claimed: Arc<AtomicBool>,
+    dispatch: Option<JoinHandle<()>>,
+}
+
 impl<T: SurrealValue + Send + Sync + Unpin + 'static> Coalition<T> {
     /// Create `N` agents and spawn their listen loops under an internal
     /// `TaskTracker`, each with its own `child_token()`.
     ///
-    /// Waits for every `listen_loop` to confirm its LIVE query is registered
-    /// before returning. Each wait is bounded by [`READY_TIMEOUT`]; a stalled
-    /// or dropped listen loop yields an error instead of hanging.
+    /// Subscribes the coalition's single LIVE wake-up first
+    /// ([`Error::WakeUp`] on failure), then waits for every `listen_loop` to
+    /// confirm it is registered with the changefeed reader before returning.
+    /// Each wait is bounded by [`READY_TIMEOUT`]; a stalled or dropped listen
+    /// loop yields an error instead of hanging.
     pub async fn new(names: Vec<String>) -> Result<Self> {
         Self::new_with_ready_timeout(names, READY_TIMEOUT).await
     }
@@ -343,58 +643,106 @@ impl<T: SurrealValue + Send + Sync + Unpin + 'static> Coalition<T> {
     /// The per-agent handshake await is factored into [`await_ready`], which is
     /// unit-tested deterministically (a never-sent receiver → [`Error::ReadyTimeout`],
     /// a dropped sender → [`Error::ListenLoopDropped`]) without racing a real
-    /// LIVE-query registration.
+    /// listen-loop startup.
     pub async fn new_with_ready_timeout(
         names: Vec<String>,
         ready_timeout: Duration,
     ) -> Result<Self> {
+        Self::new_with_options(
+            names,
+            CoalitionOptions {
+                ready_timeout,
+                ..CoalitionOptions::default()
+            },
+        )
+        .await
+    }
+
+    /// Like [`Coalition::new`] with every tunable in [`CoalitionOptions`].
+    pub async fn new_with_options(names: Vec<String>, options: CoalitionOptions) -> Result<Self> {
+        Self::new_with_client(names, Client::global().await?, options).await
+    }
+
+    /// Like [`Coalition::new_with_options`] on an explicit `client` instead of
+    /// the process-wide connection. Agents, the changefeed reader and the
+    /// retention sweep all use it, so coalitions on different clients — e.g.
+    /// one per tenant database — are fully independent within one process.
+    pub async fn new_with_client(
+        names: Vec<String>,
+        client: Client,
+        options: CoalitionOptions,
+    ) -> Result<Self> {
+        let ready_timeout = options.ready_timeout;
         let agents = Arc::new(RwLock::new(HashMap::new()));
+        let mut members = HashMap::with_capacity(names.len());
         let task_tracker = TaskTracker::new();
         let cancellation_token = CancellationToken::new();
 
         // Shared MPMC delivery bus. Each agent's listen_loop holds a clone of
         // the sender; the receiver lives on the Coalition and is handed out via
-        // inbox(). Dropping our own sender below leaves only agent-held senders,
-        // so the bus closes once all agents shut down.
+        // inbox(). The Coalition keeps one sender for agents added later and
+        // drops it in shutdown(), so the bus closes once all agents shut down.
         let (inbox_tx, inbox_rx) = kanal::bounded_async::<Delivery<T>>(INBOX_CAPACITY);
 
-        let mut ready_rxs = Vec::with_capacity(names.len());
+        // Create every record before spawning anything, so a bad name fails
+        // here with nothing left running.
+        let mut created = Vec::with_capacity(names.len());
         for name in names {
-            let agent = Agent::new(&name).await?;
-            agents.write().await.insert(name.clone(), agent.clone());
+            created.push(Agent::new_with_client(&name, &client).await?);
+        }
 
-            let token = cancellation_token.child_token();
-            let (ready_tx, ready_rx) = oneshot::channel();
-            ready_rxs.push((name.clone(), ready_rx));
+        // One LIVE wake-up and one changefeed reader for the whole coalition.
+        // Subscribed before any listen loop starts, so a failure surfaces
+        // directly and every agent that registers is already covered by it.
+        let wake = changefeed::subscribe(&client.db()).await?;
+        let reader = Arc::new(ChangefeedReader::default());
+        task_tracker.spawn(
+            changefeed::reader_loop(
+                reader.clone(),
+                client.clone(),
+                wake,
+                cancellation_token.child_token(),
+            )
+            .instrument(tracing::info_span!("changefeed")),
+        );
 
-            let span = tracing::info_span!("agent", name = %name);
-            task_tracker.spawn(
-                agent
-                    .listen_loop::<T>(token, ready_tx, inbox_tx.clone())
-                    .instrument(span),
+        let mut ready_rxs = Vec::with_capacity(created.len());
+        for agent in created {
+            let name = agent.name.clone();
+            agents.write().await.insert(name.clone(), agent.clone());
+
+            let (member, ready_rx) = spawn_member(
+                &task_tracker,
+                &cancellation_token,
+                agent,
+                inbox_tx.clone(),
+                reader.clone(),
+                &options,
             );
+            members.insert(name.clone(), member);
+            ready_rxs.push((name, ready_rx));
         }
 
-        // Drop our sender so only agent-held senders remain; the bus then closes
-        // (recv returns Err) once every agent's listen_loop has shut down.
-        drop(inbox_tx);
-
         // One table-wide retention sweep per coalition ages out the durable
         // message log (agents never delete messages). Runs under the same
         // TaskTracker + a child token, so cancel→close→wait drains it too.
-        let sweep_token = cancellation_token.child_token();
-        let retention = Duration::from_secs(SETTINGS.sdb.message_retention_secs);
-        task_tracker.spawn(
-            retention_sweep(sweep_token, retention)
-                .instrument(tracing::info_span!("retention_sweep")),
-        );
+        // A record session may not delete rows; a system user's client
+        // sweeps instead.
+        if client.is_system_user() {
+            let sweep_token = cancellation_token.child_token();
+            let retention = Duration::from_secs(client.settings().sdb.message_retention_secs);
+            task_tracker.spawn(
+                retention_sweep(client.clone(), sweep_token, retention)
+                    .instrument(tracing::info_span!("retention_sweep")),
+            );
+        }
 
-        // Wait until every listen_loop has confirmed its LIVE query is
-        // registered with the server. Without this handshake, Coalition::new
-        // could return before subscriptions exist and the first Agent::send
-        // calls would be lost. Each await is bounded by READY_TIMEOUT so an
-        // unresponsive DB stalling LIVE registration (sender neither sent nor
-        // dropped) cannot hang Coalition::new forever.
+        // Wait until every listen_loop has registered with the reader.
+        // Without this handshake, Coalition::new could return before an agent
+        // has a read position and the first Agent::send calls would be lost.
+        // Each await is bounded by READY_TIMEOUT so an unresponsive DB
+        // stalling cursor setup (sender neither sent nor dropped) cannot hang
+        // Coalition::new forever.
         for (name, rx) in ready_rxs {
             let Err(err) = await_ready(name, rx, ready_timeout).await else {
                 continue;
@@ -413,18 +761,211 @@ impl<T: SurrealValue + Send + Sync + Unpin + 'static> Coalition<T> {
 
         Ok(Self {
             agents,
+            members: AsyncMutex::new(members),
             task_tracker,
             cancellation_token,
             inbox: inbox_rx,
+            inbox_tx: Mutex::new(Some(inbox_tx)),
+            reader,
+            client,
+            options,
             _payload: PhantomData,
         })
     }
 
+    /// Add an agent to a running coalition: create (or reuse) its record and
+    /// spawn its listen loop with the same readiness handshake as
+    /// [`Coalition::new`], so a send issued after this returns is observed.
+    ///
+    /// Returns [`Error::DuplicateAgent`] if `name` is already a member and
+    /// [`Error::CoalitionClosed`] after [`Coalition::shutdown`]. A failed
+    /// handshake drains the new listen loop and leaves the coalition as it was.
+    pub async fn add_agent(&self, name: &str) -> Result<Agent> {
+        let mut members = self.members.lock().await;
+        if members.contains_key(name) {
+            return Err(Error::DuplicateAgent {
+                agent: name.to_string(),
+            });
+        }
+        let inbox_tx = self
+            .inbox_tx
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+            .ok_or(Error::CoalitionClosed)?;
+
+        let agent = Agent::new_with_client(name, &self.client).await?;
+        let (member, ready_rx) = spawn_member(
+            &self.task_tracker,
+            &self.cancellation_token,
+            agent.clone(),
+            inbox_tx,
+            self.reader.clone(),
+            &self.options,
+        );
+        if let Err(err) = await_ready(name.to_string(), ready_rx, self.options.ready_timeout).await
+        {
+            member.token.cancel();
+            let _ = member.handle.await;
+            return Err(err);
+        }
+
+        members.insert(name.to_string(), member);
+        self.agents
+            .write()
+            .await
+            .insert(name.to_string(), agent.clone());
+        Ok(agent)
+    }
+
+    /// Remove one agent from a running coalition: cancel only its child token
+    /// and wait for its listen loop to drain. The other agents, the bus and the
+    /// agent's durable record + cursor are untouched, so re-adding it later
+    /// resumes where it left off.
+    ///
+    /// Returns [`Error::NotAMember`] if `name` is not in the coalition.
+    pub async fn remove_agent(&self, name: &str) -> Result<()> {
+        let mut members = self.members.lock().await;
+        let member = members.remove(name).ok_or_else(|| Error::NotAMember {
+            agent: name.to_string(),
+        })?;
+        self.agents.write().await.remove(name);
+
+        member.token.cancel();
+        match member.handle.await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => tracing::warn!("listen_loop for {name} exited with error: {e}"),
+            Err(e) => tracing::warn!("listen_loop for {name} panicked: {e}"),
+        }
+        if let Some(dispatch) = member.dispatch
+            && let Err(e) = dispatch.await
+        {
+            tracing::warn!("dispatch loop for {name} panicked: {e}");
+        }
+        Ok(())
+    }
+
+    /// Subscribe member `name` to `topic`: it receives every
+    /// [`Agent::publish`] to the topic as a [`Delivery`] with `topic` set,
+    /// starting with the publications still retained in the changefeed
+    /// ([`SubscribeFrom::Earliest`]), so a late subscriber replays the
+    /// retention window. The subscription is a durable `subscribes` edge with
+    /// its own cursor, so it survives restarts and resumes where it left off,
+    /// and it is acknowledged under the coalition's [`AckMode`] like a direct
+    /// message. Subscribing twice is a no-op.
+    ///
+    /// Returns [`Error::NotAMember`] for an unknown name and
+    /// [`Error::InvalidTopicName`] for a bad topic name.
+    pub async fn subscribe(&self, name: &str, topic: &str) -> Result<()> {
+        self.subscribe_from(name, topic, SubscribeFrom::default())
+            .await
+    }
+
+    /// Like [`Coalition::subscribe`], starting where `from` says;
+    /// [`SubscribeFrom::Latest`] skips what was published before.
+    pub async fn subscribe_from(&self, name: &str, topic: &str, from: SubscribeFrom) -> Result<()> {
+        topic::validate(topic)?;
+        let members = self.members.lock().await;
+        if !members.contains_key(name) {
+            return Err(Error::NotAMember {
+                agent: name.to_string(),
+            });
+        }
+        if let Some(start) =
+            topic::create(&self.client.db(), &self.reader, name, topic, from).await?
+        {
+            self.reader.add_topic(name, topic, start).await;
+        }
+        Ok(())
+    }
+
+    /// Drop member `name`'s subscription to `topic`, cursor included; a later
+    /// [`Coalition::subscribe`] starts over as a new subscription.
+    /// Publications still awaiting an ack are no longer redelivered. Returns
+    /// [`Error::NotAMember`] for an unknown name.
+    pub async fn unsubscribe(&self, name: &str, topic: &str) -> Result<()> {
+        topic::validate(topic)?;
+        let members = self.members.lock().await;
+        if !members.contains_key(name) {
+            return Err(Error::NotAMember {
+                agent: name.to_string(),
+            });
+        }
+        topic::delete(&self.client.db(), name, topic).await?;
+        self.reader.remove_topic(name, topic).await;
+        Ok(())
+    }
+
+    /// Register `handler` for agent `name` and let the coalition run its
+    /// receive loop, with [`CoalitionOptions::handler_concurrency`] deliveries
+    /// in flight at most. See [`crate::subsystems::dispatch`].
+    pub async fn register_handler<H>(&self, name: &str, handler: H) -> Result<()>
+    where
+        H: AgentHandler<T>,
+    {
+        self.register_handler_with_concurrency(name, handler, self.options.handler_concurrency)
+            .await
+    }
+
+    /// Like [`Coalition::register_handler`] with an explicit per-agent
+    /// concurrency limit.
+    ///
+    /// The handler takes over the agent's own inbox ([`Coalition::inbox_for`]),
+    /// so that agent's deliveries no longer reach the shared
+    /// [`Coalition::inbox`]. The dispatch loop runs under the coalition's
+    /// `TaskTracker` on a child of the agent's token: [`Coalition::remove_agent`]
+    /// and [`Coalition::shutdown`] stop it and drain in-flight handlers.
+    /// Returns [`Error::NotAMember`] for an unknown name and
+    /// [`Error::HandlerExists`] if the agent already has a handler.
+    pub async fn register_handler_with_concurrency<H>(
+        &self,
+        name: &str,
+        handler: H,
+        concurrency: usize,
+    ) -> Result<()>
+    where
+        H: AgentHandler<T>,
+    {
+        let mut members = self.members.lock().await;
+        let not_a_member = || Error::NotAMember {
+            agent: name.to_string(),
+        };
+        let member = members.get_mut(name).ok_or_else(not_a_member)?;
+        if member.dispatch.is_some() {
+            return Err(Error::HandlerExists {
+                agent: name.to_string(),
+            });
+        }
+        let agent = self.agent(name).await.ok_or_else(not_a_member)?;
+
+        member.claimed.store(true, Ordering::Release);
+        let span = tracing::info_span!("dispatch", name = %name);
+        member.dispatch = Some(
+            self.task_tracker.spawn(
+                dispatch_loop(
+                    Arc::new(handler),
+                    agent,
+                    member.inbox.clone(),
+                    concurrency,
+                    self.task_tracker.clone(),
+                    member.token.child_token(),
+                )
+                .instrument(span),
+            ),
+        );
+        Ok(())
+    }
+
     /// Look up an agent by name for send calls.
     pub async fn agent(&self, name: &str) -> Option<Agent> {
         self.agents.read().await.get(name).cloned()
     }
 
+    /// The client this coalition's agents talk through.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
     /// Root cancellation token — downstream binaries wire top-level shutdown
     /// onto this.
     pub fn cancellation_token(&self) -> &CancellationToken {
@@ -438,14 +979,84 @@ impl<T: SurrealValue + Send + Sync + Unpin + 'static> Coalition<T> {
         self.inbox.clone()
     }
 
+    /// A receiver yielding only `name`'s deliveries, with its own capacity
+    /// ([`CoalitionOptions::agent_inbox_capacity`]) and backpressure: a slow
+    /// consumer here stalls only this agent's listen loop, never the others.
+    ///
+    /// The first call switches the agent's deliveries off the shared
+    /// [`Coalition::inbox`] for good; call it before sending to the agent so no
+    /// delivery lands on the shared bus first. Later calls return clones of the
+    /// same MPMC receiver. `recv()` returns `Err` once the agent is removed or
+    /// the coalition shuts down. Returns [`Error::NotAMember`] for an unknown
+    /// name.
+    pub async fn inbox_for(&self, name: &str) -> Result<AsyncReceiver<Delivery<T>>> {
+        let members = self.members.lock().await;
+        let member = members.get(name).ok_or_else(|| Error::NotAMember {
+            agent: name.to_string(),
+        })?;
+        member.claimed.store(true, Ordering::Release);
+        Ok(member.inbox.clone())
+    }
+
     /// Three-step shutdown: cancel → close → drain.
     pub async fn shutdown(&self) {
+        // Release the coalition's own bus sender so the bus closes once the
+        // listen loops drain, and no agent can join afterwards.
+        self.inbox_tx
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
         self.cancellation_token.cancel();
         self.task_tracker.close();
         self.task_tracker.wait().await;
     }
 }
 
+/// Spawn `agent`'s listen loop under `task_tracker` with its own child of
+/// `parent`, returning the member handle and its readiness receiver.
+fn spawn_member<T>(
+    task_tracker: &TaskTracker,
+    parent: &CancellationToken,
+    agent: Agent,
+    inbox_tx: AsyncSender<Delivery<T>>,
+    reader: Arc<ChangefeedReader>,
+    options: &CoalitionOptions,
+) -> (Member<T>, oneshot::Receiver<()>)
+where
+    T: SurrealValue + Send + Sync + Unpin + 'static,
+{
+    let token = parent.child_token();
+    let (ready_tx, ready_rx) = oneshot::channel();
+    let (own_tx, own_rx) = kanal::bounded_async(options.agent_inbox_capacity);
+    let claimed = Arc::new(AtomicBool::new(false));
+    let sink = DeliverySink {
+        shared: inbox_tx,
+        own: own_tx,
+        claimed: claimed.clone(),
+    };
+    let span = tracing::info_span!("agent", name = %agent.name);
+    let handle = task_tracker.spawn(
+        agent
+            .listen_loop::<T>(
+                token.clone(),
+                ready_tx,
+                sink,
+                options.ack_mode,
+                options.max_delivery_attempts,
+                reader,
+            )
+            .instrument(span),
+    );
+    let member = Member {
+        token,
+        handle,
+        inbox: own_rx,
+        claimed,
+        dispatch: None,
+    };
+    (member, ready_rx)
+}
+
 /// Await one listen-loop's readiness signal, bounded by `ready_timeout`, mapping
 /// the three outcomes onto the typed handshake errors:
 ///
@@ -456,7 +1067,7 @@ impl<T: SurrealValue + Send + Sync + Unpin + 'static> Coalition<T> {
 /// Pure async logic over a `oneshot` — no DB — so the two error paths are
 /// unit-testable deterministically (see the tests below). The integration test
 /// can't exercise `ReadyTimeout` reliably: [`tokio::time::timeout`] polls the
-/// inner future before the timer, so a real LIVE-query registration that wins
+/// inner future before the timer, so a real listen-loop registration that wins
 /// the same park cycle resolves the receiver and yields `Ok` regardless of how
 /// small the timeout is.
 async fn await_ready(
@@ -484,18 +1095,6 @@ struct CursorRow {
     versionstamp: i64,
 }
 
-/// Pull the `versionstamp` out of a `cursor` record (or a `SHOW CHANGES`
-/// changeset) `Value::Object`.
-fn versionstamp_of(v: &Value) -> Option<i64> {
-    match v {
-        Value::Object(o) => match o.get("versionstamp") {
-            Some(Value::Number(n)) => n.to_int(),
-            _ => None,
-        },
-        _ => None,
-    }
-}
-
 /// Load the persisted high-water-mark cursor for `agent`, if any.
 async fn load_cursor(db: &Surreal<any::Any>, agent: &str) -> Result<Option<i64>> {
     let row: Option<Value> =
@@ -522,136 +1121,297 @@ async fn save_cursor(db: &Surreal<any::Any>, agent: &str, versionstamp: i64) ->
     Ok(())
 }
 
-/// Largest `versionstamp` in a `SHOW CHANGES` result array (0 if empty/none).
-fn max_versionstamp(v: &Value) -> i64 {
-    let Value::Array(arr) = v else { return 0 };
-    arr.iter().filter_map(versionstamp_of).max().unwrap_or(0)
-}
-
-/// Latest versionstamp currently retained in the message changefeed, for the
-/// first-run "start from now" snapshot. Reads (and discards) the retained window
-/// once; returns 0 when the feed is empty. Only runs when an agent has no
-/// persisted cursor yet.
-async fn latest_versionstamp(db: &Surreal<any::Any>, agent: &str) -> Result<i64> {
-    let q = format!("SHOW CHANGES FOR TABLE {MESSAGE_TABLE} SINCE 0 LIMIT 1000000");
-    let v: Value = db
-        .query(&q)
-        .await
-        .map_err(|source| Error::CatchUp {
-            agent: agent.to_string(),
-            source,
-        })?
-        .take(0)
-        .map_err(|source| Error::CatchUp {
-            agent: agent.to_string(),
-            source,
-        })?;
-    Ok(max_versionstamp(&v))
+/// A listen loop's in-memory read positions: `cursor:<agent>`'s and each
+/// topic subscription's. Without an ack tracker they are also what gets
+/// persisted.
+#[derive(Debug)]
+struct ReadPositions {
+    messages: i64,
+    topics: HashMap<String, i64>,
 }
 
-/// Drain the durable log from `*cursor` and deliver every message addressed to
-/// `owner`, advancing + persisting the cursor past everything seen. Bounded and
-/// resumable; loops until a short page signals the live edge is reached. Because
-/// `cursor = max_seen + 1`, a delivered message is never re-read (no dedup set
-/// needed); `SHOW CHANGES` is table-wide, so non-owner changes are filtered out.
-async fn catch_up<T>(
+/// Deliver one reader batch and advance + persist the cursor it was read
+/// from — `cursor:<agent>`, or the topic subscription's for a `publication`
+/// batch. Because `cursor = max_seen + 1`, a delivered record is never re-read
+/// (no dedup set needed).
+///
+/// With an ack tracker (`AckMode::Manual`) `read` only holds read positions;
+/// each delivery is tracked and the persisted cursors are left to
+/// [`commit_cursor`].
+async fn deliver_batch<T>(
     db: &Surreal<any::Any>,
-    owner: &RecordId,
     agent: &str,
-    cursor: &mut i64,
-    inbox_tx: &AsyncSender<Delivery<T>>,
+    batch: Batch,
+    read: &mut ReadPositions,
+    acks: &mut Option<AckTracker>,
+    sink: &DeliverySink<T>,
 ) -> Result<()>
 where
     T: SurrealValue + Send + Sync + Unpin + 'static,
 {
-    loop {
-        let q = format!(
-            "SHOW CHANGES FOR TABLE {MESSAGE_TABLE} SINCE {} LIMIT {CATCHUP_BATCH}",
-            *cursor
-        );
-        let v: Value = db
-            .query(&q)
-            .await
-            .map_err(|source| Error::CatchUp {
-                agent: agent.to_string(),
-                source,
-            })?
-            .take(0)
-            .map_err(|source| Error::CatchUp {
-                agent: agent.to_string(),
-                source,
-            })?;
-        let Value::Array(changesets) = v else { break };
-        let page = changesets.len();
-        if page == 0 {
-            break;
+    let topic = batch.topic.as_deref();
+    for (vs, record) in batch.changes {
+        // A reply to one of this agent's pending requests goes straight to the
+        // waiting future, never onto the bus.
+        if topic.is_none() && route_reply(agent, &record) {
+            continue;
         }
-
-        let mut max_vs = *cursor - 1;
-        for changeset in changesets.iter() {
-            let Value::Object(obj) = changeset else {
+        let message = match Message::<T>::from_value(record.clone()) {
+            Ok(m) => m,
+            Err(e) => {
+                tracing::warn!("dead-lettering undecodable change for {agent}: {e}");
+                if let Err(e) = dead_letter::record(db, agent, record, e.to_string(), vs).await {
+                    tracing::error!("{e}");
+                }
                 continue;
-            };
-            if let Some(vs) = versionstamp_of(changeset) {
-                max_vs = max_vs.max(vs);
             }
-            let Some(Value::Array(changes)) = obj.get("changes") else {
-                continue;
-            };
-            for change in changes.iter() {
-                let Value::Object(op) = change else { continue };
-                // RELATE-created edges surface as a "create"/"update" op carrying
-                // the full record; "delete" ops (retention sweep) are skipped.
-                let Some(record) = op.get("update").or_else(|| op.get("create")) else {
-                    continue;
-                };
-                let message = match Message::<T>::from_value(record.clone()) {
-                    Ok(m) => m,
-                    Err(e) => {
-                        tracing::warn!("skipping undecodable change for {agent}: {e}");
-                        continue;
-                    }
-                };
-                if message.out.as_ref() != Some(owner) {
-                    continue; // table-wide feed — keep only ours
-                }
-                tracing::info!(target = %agent, from = ?message.r#in, "message delivered");
-                if let Err(e) = inbox_tx
-                    .send(Delivery {
-                        recipient: agent.to_string(),
-                        message,
-                    })
-                    .await
-                {
-                    tracing::debug!("inbox bus closed for {agent}: {e}");
-                }
+        };
+        let acker = match acks.as_mut() {
+            Some(tracker) => tracker.track(topic, vs, record, Instant::now()),
+            None => Acker::auto(),
+        };
+        match topic {
+            Some(topic) => {
+                tracing::info!(target = %agent, topic, from = ?message.r#in, "publication delivered")
             }
+            None => tracing::info!(target = %agent, from = ?message.r#in, "message delivered"),
         }
+        sink.send(
+            agent,
+            Delivery {
+                recipient: agent.to_string(),
+                topic: batch.topic.clone(),
+                message,
+                attempt: 1,
+                acker,
+            },
+        )
+        .await;
+    }
 
-        if max_vs >= *cursor {
-            *cursor = max_vs + 1;
-            save_cursor(db, agent, *cursor).await?;
+    match topic {
+        Some(topic) => {
+            read.topics.insert(topic.to_string(), batch.read_cursor);
+            commit_cursor(db, agent, Some(topic), batch.read_cursor, acks).await
         }
-        if page < CATCHUP_BATCH {
-            break;
+        None if batch.read_cursor > read.messages => {
+            read.messages = batch.read_cursor;
+            commit_cursor(db, agent, None, read.messages, acks).await
         }
+        None => Ok(()),
+    }
+}
+
+/// Persist `topic`'s cursor (`cursor:<agent>` for `None`) after its read
+/// position moved to `read_cursor`. Without a tracker that is `read_cursor`
+/// itself; with one, only the acknowledged prefix is committed and nothing is
+/// written unless it moved.
+async fn commit_cursor(
+    db: &Surreal<any::Any>,
+    agent: &str,
+    topic: Option<&str>,
+    read_cursor: i64,
+    acks: &mut Option<AckTracker>,
+) -> Result<()> {
+    let point = match acks.as_ref() {
+        None => read_cursor,
+        Some(tracker) => match tracker.pending_commit(topic, read_cursor) {
+            Some(point) => point,
+            None => return Ok(()),
+        },
+    };
+    match topic {
+        Some(topic) => topic::save_cursor(db, agent, topic, point).await?,
+        None => save_cursor(db, agent, point).await?,
+    }
+    if let Some(tracker) = acks.as_mut() {
+        tracker.set_committed(topic, point);
+    }
+    Ok(())
+}
+
+/// [`commit_cursor`] for the message cursor and every topic subscription.
+async fn commit_cursors(
+    db: &Surreal<any::Any>,
+    agent: &str,
+    read: &ReadPositions,
+    acks: &mut Option<AckTracker>,
+) -> Result<()> {
+    commit_cursor(db, agent, None, read.messages, acks).await?;
+    for (topic, &read_cursor) in &read.topics {
+        commit_cursor(db, agent, Some(topic), read_cursor, acks).await?;
     }
     Ok(())
 }
 
-/// Periodic age-out sweep: delete message rows older than the retention window.
+/// Apply an ack/nack (plus any others already queued), redeliver nacked
+/// deliveries right away, then commit whatever prefix is now acknowledged.
+async fn on_ack_event<T>(
+    db: &Surreal<any::Any>,
+    agent: &str,
+    first: AckEvent,
+    ack_rx: &mut mpsc::UnboundedReceiver<AckEvent>,
+    read: &ReadPositions,
+    acks: &mut Option<AckTracker>,
+    sink: &DeliverySink<T>,
+) -> Result<()>
+where
+    T: SurrealValue + Send + Sync + Unpin + 'static,
+{
+    let Some(tracker) = acks.as_mut() else {
+        return Ok(());
+    };
+    let now = Instant::now();
+    let mut nacked = matches!(first, AckEvent::Nack(_));
+    tracker.apply(first, now);
+    while let Ok(event) = ack_rx.try_recv() {
+        nacked |= matches!(event, AckEvent::Nack(_));
+        tracker.apply(event, now);
+    }
+    if nacked {
+        redeliver_expired::<T>(db, agent, acks, sink).await;
+    }
+    commit_cursors(db, agent, read, acks).await
+}
+
+/// Re-send every tracked delivery whose visibility timeout expired (or that
+/// was nacked). The record decoded on first delivery, so it decodes again.
+/// One past its attempt limit is dead-lettered and acked instead; if that
+/// write fails it stays tracked and is retried on its next expiry.
+async fn redeliver_expired<T>(
+    db: &Surreal<any::Any>,
+    agent: &str,
+    acks: &mut Option<AckTracker>,
+    sink: &DeliverySink<T>,
+) where
+    T: SurrealValue + Send + Sync + Unpin + 'static,
+{
+    let Some(tracker) = acks.as_mut() else {
+        return;
+    };
+    for redelivery in tracker.take_expired(Instant::now()) {
+        if tracker.exhausted(&redelivery) {
+            let reason = format!(
+                "not acknowledged after {} delivery attempts",
+                redelivery.attempt - 1
+            );
+            tracing::warn!("dead-lettering delivery for {agent}: {reason}");
+            match dead_letter::record(
+                db,
+                agent,
+                redelivery.record,
+                reason,
+                redelivery.versionstamp,
+            )
+            .await
+            {
+                Ok(()) => redelivery.acker.ack(),
+                Err(e) => tracing::error!("{e}"),
+            }
+            continue;
+        }
+        let Redelivery {
+            acker,
+            topic,
+            record,
+            attempt,
+            ..
+        } = redelivery;
+        let message = match Message::<T>::from_value(record) {
+            Ok(m) => m,
+            Err(e) => {
+                tracing::warn!("skipping undecodable redelivery for {agent}: {e}");
+                continue;
+            }
+        };
+        tracing::info!(target = %agent, topic = topic.as_deref(), from = ?message.r#in, attempt, "message redelivered");
+        sink.send(
+            agent,
+            Delivery {
+                recipient: agent.to_string(),
+                topic,
+                message,
+                attempt,
+                acker,
+            },
+        )
+        .await;
+    }
+}
+
+/// In-flight [`Agent::request`] waiters keyed by `(requester, correlation_id)`.
+/// Process-global: an `Agent` handle carries no coalition reference, while the
+/// requester's listen loop is what sees the reply. Correlation ids are UUIDs,
+/// so agents of the same name on different clients never collide.
+static REPLY_WAITERS: LazyLock<Mutex<HashMap<(String, String), oneshot::Sender<Value>>>> =
+    LazyLock::new(Default::default);
+
+/// One registered reply waiter; deregisters on drop, so a request that times
+/// out or fails to send leaves nothing behind.
+struct ReplyWaiter {
+    key: (String, String),
+}
+
+impl ReplyWaiter {
+    fn register(agent: &str, correlation_id: &str, tx: oneshot::Sender<Value>) -> Self {
+        let key = (agent.to_string(), correlation_id.to_string());
+        REPLY_WAITERS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(key.clone(), tx);
+        Self { key }
+    }
+}
+
+impl Drop for ReplyWaiter {
+    fn drop(&mut self) {
+        REPLY_WAITERS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.key);
+    }
+}
+
+/// Hand `record` to `agent`'s waiting request if it is a reply to one.
+/// Returns `false` (deliver normally) when it isn't a reply or nobody waits.
+fn route_reply(agent: &str, record: &Value) -> bool {
+    let Value::Object(obj) = record else {
+        return false;
+    };
+    let Some(Value::String(reply_to)) = obj.get("reply_to") else {
+        return false;
+    };
+    let waiter = REPLY_WAITERS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(&(agent.to_string(), reply_to.clone()));
+    waiter.is_some_and(|tx| tx.send(record.clone()).is_ok())
+}
+
+/// The name behind an `agent:<name>` id, for error labels.
+fn agent_name(id: &RecordId) -> String {
+    match &id.key {
+        RecordIdKey::String(name) => name.clone(),
+        _ => id.to_sql(),
+    }
+}
+
+/// Periodic age-out sweep: delete message and publication rows older than the
+/// retention window.
 /// One task per coalition (table-wide); the durable log is otherwise never
 /// pruned. Runs every `retention/4` (min 60s) until cancelled.
-async fn retention_sweep(token: CancellationToken, retention: Duration) {
-    let db = sdb::SurrealDBWrapper::connection().await;
+async fn retention_sweep(client: Client, token: CancellationToken, retention: Duration) {
     let interval = (retention / 4).max(Duration::from_secs(60));
     let secs = retention.as_secs();
-    let q = format!("DELETE {MESSAGE_TABLE} WHERE created < time::now() - {secs}s");
+    let q = format!(
+        "DELETE {MESSAGE_TABLE} WHERE created < time::now() - {secs}s; \
+         DELETE {PUBLICATION_TABLE} WHERE created < time::now() - {secs}s;"
+    );
     loop {
         tokio::select! {
             _ = token.cancelled() => break,
             _ = sleep(interval) => {
-                if let Err(e) = db.query(&q).await.and_then(|r| r.check()) {
+                if let Err(e) = client.db().query(&q).await.and_then(|r| r.check()) {
                     tracing::warn!("retention sweep failed: {e}");
                 }
             }
@@ -659,20 +1419,6 @@ async fn retention_sweep(token: CancellationToken, retention: Duration) {
     }
 }
 
-/// Sleep for `dur` unless cancelled first. Returns `true` if cancellation won
-/// (the caller should stop).
-async fn cancel_or_sleep(token: &CancellationToken, dur: Duration) -> bool {
-    tokio::select! {
-        _ = token.cancelled() => true,
-        _ = sleep(dur) => false,
-    }
-}
-
-/// Capped exponential backoff step.
-fn next_backoff(current: Duration) -> Duration {
-    (current * 2).min(RECONNECT_BACKOFF_MAX)
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,584 @@
+//! Coalition-level reader over the `message` and `publication` changefeeds.
+//!
+//! Each coalition runs one [`reader_loop`]: a single wake-up (a `LIVE SELECT`
+//! on each of `message` and `publication`) and a single `SHOW CHANGES` reader,
+//! however many agents it has. Listen loops
+//! neither subscribe nor scan; they register with the [`ChangefeedReader`] and
+//! deliver the batches it hands them, so a message costs one wake and one scan
+//! per coalition instead of one per agent.
+//!
+//! `SHOW CHANGES FOR TABLE message` is table-wide — SurrealDB cannot filter a
+//! changefeed by recipient — so scanning it once per agent made every wake
+//! O(total traffic) per agent, and an N-agent coalition read the whole log N
+//! times. A [`ChangefeedReader`] is shared by all of a coalition's listen loops
+//! instead: one scan pages through the log once and demultiplexes each change
+//! by its `out` to the agent it is addressed to, as a [`Batch`] on that agent's
+//! channel.
+//!
+//! Agents keep their own persisted cursors. The reader scans from the lowest
+//! registered read position and hands each agent only changes at or past its
+//! own position, so an agent that joins with an older cursor replays exactly
+//! its own backlog without re-delivering anyone else's.
+//!
+//! Topic publications ([`crate::topic`]) work the same way over the
+//! `publication` feed, with one cursor per (agent, topic): a record goes to
+//! every subscriber of its topic, each as a [`Batch`] tagged with the topic.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use futures::StreamExt;
+use futures::stream::BoxStream;
+use surrealdb::engine::any;
+use surrealdb::{Notification, Surreal};
+use surrealdb_types::{RecordId, Value};
+use tokio::sync::{Mutex, Notify, mpsc};
+use tokio::time::{Duration, sleep};
+use tokio_util::sync::CancellationToken;
+
+use crate::client::{Client, ConnectionEvent};
+use crate::error::{Error, Result};
+use crate::message::MESSAGE_TABLE;
+use crate::topic::{PUBLICATION_TABLE, Subscription, TOPIC_TABLE};
+
+/// Max changesets pulled per `SHOW CHANGES` page.
+pub(crate) const CATCHUP_BATCH: usize = 1000;
+
+/// Reconnect backoff bounds for the LIVE wake-up subscription.
+const RECONNECT_BACKOFF_START: Duration = Duration::from_millis(200);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// The coalition's LIVE wake-up subscription.
+pub(crate) type WakeStream =
+    BoxStream<'static, std::result::Result<Notification<Value>, surrealdb::Error>>;
+
+/// One scan's worth of changes for one agent.
+#[derive(Debug)]
+pub(crate) struct Batch {
+    /// `(versionstamp, record)` for every created/updated edge addressed to
+    /// the agent, in log order.
+    pub(crate) changes: Vec<(i64, Value)>,
+    /// The agent's next unread versionstamp after this batch (`max_seen + 1`).
+    pub(crate) read_cursor: i64,
+    /// `Some(topic)` for `publication` records from one subscribed topic,
+    /// with `read_cursor` in the `publication` feed; `None` for `message`.
+    pub(crate) topic: Option<String>,
+}
+
+/// What the reader hands a listen loop.
+#[derive(Debug)]
+pub(crate) enum ReaderEvent {
+    /// Changes to deliver.
+    Batch(Batch),
+    /// The agent stopped following this topic; publications from it that are
+    /// still awaiting an ack no longer hold back a cursor.
+    Unsubscribed(String),
+}
+
+/// A registered listen loop: whose messages it takes, how far it has read,
+/// which topics it follows, and where its batches go.
+#[derive(Debug)]
+struct Subscriber {
+    owner: RecordId,
+    position: i64,
+    topics: Vec<TopicCursor>,
+    tx: mpsc::UnboundedSender<ReaderEvent>,
+}
+
+/// One subscriber's read position in one topic's share of the `publication`
+/// feed.
+#[derive(Debug)]
+struct TopicCursor {
+    name: String,
+    id: RecordId,
+    position: i64,
+}
+
+impl TopicCursor {
+    fn new(name: String, position: i64) -> Self {
+        let id = RecordId::new(TOPIC_TABLE, name.as_str());
+        Self { name, id, position }
+    }
+}
+
+/// Shared `SHOW CHANGES` reader for one coalition.
+///
+/// Batch channels are unbounded so one slow agent never stalls the scan for
+/// the rest, at the cost of buffering a slow agent's changes in memory (see
+/// `CoalitionOptions`).
+#[derive(Debug, Default)]
+pub(crate) struct ChangefeedReader {
+    /// Held across a whole scan, so a registration waits for it to finish.
+    subscribers: Mutex<HashMap<String, Subscriber>>,
+    /// Asks [`reader_loop`] for a scan without a LIVE notification — e.g. to
+    /// replay a newly registered agent's backlog.
+    wake: Notify,
+    /// Furthest read position seen in each feed, so
+    /// [`next_versionstamp`](Self::next_versionstamp) reads only what came
+    /// after it.
+    message_tail: AtomicI64,
+    publication_tail: AtomicI64,
+}
+
+impl ChangefeedReader {
+    /// Register `agent` to receive changes addressed to `owner` from
+    /// versionstamp `position` on, plus publications to each of `topics`
+    /// from its own cursor, and schedule a scan for its backlog.
+    /// Re-registering a name replaces the old entry; a dropped receiver is
+    /// pruned on the next scan.
+    pub(crate) async fn register(
+        &self,
+        agent: &str,
+        owner: RecordId,
+        position: i64,
+        topics: Vec<Subscription>,
+    ) -> mpsc::UnboundedReceiver<ReaderEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let topics = topics
+            .into_iter()
+            .map(|s| TopicCursor::new(s.topic, s.versionstamp))
+            .collect();
+        self.subscribers.lock().await.insert(
+            agent.to_string(),
+            Subscriber {
+                owner,
+                position,
+                topics,
+                tx,
+            },
+        );
+        self.wake.notify_one();
+        rx
+    }
+
+    /// Start handing `agent` publications to `topic` from `position` on, and
+    /// schedule a scan for what the topic has retained. No-op if `agent` is
+    /// not registered or already follows `topic`.
+    pub(crate) async fn add_topic(&self, agent: &str, topic: &str, position: i64) {
+        let mut subscribers = self.subscribers.lock().await;
+        let Some(s) = subscribers.get_mut(agent) else {
+            return;
+        };
+        if s.topics.iter().all(|t| t.name != topic) {
+            s.topics.push(TopicCursor::new(topic.to_string(), position));
+        }
+        drop(subscribers);
+        self.wake.notify_one();
+    }
+
+    /// Stop handing `agent` publications to `topic`, and tell its listen loop.
+    pub(crate) async fn remove_topic(&self, agent: &str, topic: &str) {
+        if let Some(s) = self.subscribers.lock().await.get_mut(agent) {
+            s.topics.retain(|t| t.name != topic);
+            let _ = s.tx.send(ReaderEvent::Unsubscribed(topic.to_string()));
+        }
+    }
+
+    /// Page through both changefeeds up to the live edge, sending every
+    /// subscriber its share of each page. Bounded and resumable: each feed
+    /// loops until a short page signals the edge.
+    ///
+    /// Only subscribers with changes in a page get a batch for it, so a
+    /// message costs one cursor write (its recipient's), not one per agent.
+    /// The others' read positions still move past the page in memory; their
+    /// persisted cursors lag until their next change, and a restart rereads
+    /// (and skips) the traffic in between.
+    async fn drain(&self, db: &Surreal<any::Any>) -> Result<()> {
+        let mut subscribers = self.subscribers.lock().await;
+        subscribers.retain(|_, s| !s.tx.is_closed());
+        drain_messages(db, &mut subscribers, &self.message_tail).await?;
+        drain_publications(db, &mut subscribers, &self.publication_tail).await
+    }
+
+    /// Next unread versionstamp at the live edge of `table`'s changefeed: a
+    /// new cursor there reads only what comes after. Pages forward from the
+    /// furthest position this reader has seen in `table`, so it reads the
+    /// tail of the retention window rather than all of it.
+    pub(crate) async fn next_versionstamp(
+        &self,
+        db: &Surreal<any::Any>,
+        table: &str,
+    ) -> std::result::Result<i64, surrealdb::Error> {
+        let tail = if table == PUBLICATION_TABLE {
+            &self.publication_tail
+        } else {
+            &self.message_tail
+        };
+        let mut since = tail.load(Ordering::Acquire);
+        loop {
+            let q = format!("SHOW CHANGES FOR TABLE {table} SINCE {since} LIMIT {CATCHUP_BATCH}");
+            let v: Value = db.query(&q).await?.take(0)?;
+            let Value::Array(changesets) = &v else { break };
+            if changesets.is_empty() {
+                break;
+            }
+            since = since.max(max_versionstamp(&v) + 1);
+            if changesets.len() < CATCHUP_BATCH {
+                break;
+            }
+        }
+        tail.fetch_max(since, Ordering::AcqRel);
+        Ok(since)
+    }
+}
+
+/// The `message` half of [`ChangefeedReader::drain`]: each record goes to the
+/// one subscriber its `out` names.
+async fn drain_messages(
+    db: &Surreal<any::Any>,
+    subscribers: &mut HashMap<String, Subscriber>,
+    tail: &AtomicI64,
+) -> Result<()> {
+    let Some(mut since) = subscribers.values().map(|s| s.position).min() else {
+        return Ok(());
+    };
+    let by_owner: HashMap<RecordId, String> = subscribers
+        .iter()
+        .map(|(name, s)| (s.owner.clone(), name.clone()))
+        .collect();
+
+    loop {
+        let Some(page) = read_page(db, MESSAGE_TABLE, since)
+            .await
+            .map_err(|source| scan_error(subscribers.keys(), source))?
+        else {
+            break;
+        };
+
+        let mut batches: HashMap<&str, Vec<(i64, Value)>> = HashMap::new();
+        for (vs, record) in page.records {
+            let Some(name) = recipient_of(&record).and_then(|out| by_owner.get(out)) else {
+                continue; // not a local agent
+            };
+            if subscribers[name].position > vs {
+                continue; // already read
+            }
+            batches.entry(name.as_str()).or_default().push((vs, record));
+        }
+
+        let read_cursor = page.read_cursor;
+        for (name, changes) in batches {
+            // A closed channel means the listen loop is gone; it is pruned
+            // on the next scan and resumes from its persisted cursor.
+            let _ = subscribers[name].tx.send(ReaderEvent::Batch(Batch {
+                changes,
+                read_cursor,
+                topic: None,
+            }));
+        }
+        for s in subscribers.values_mut() {
+            s.position = s.position.max(read_cursor);
+        }
+        tail.fetch_max(read_cursor, Ordering::AcqRel);
+
+        since = read_cursor;
+        if page.changesets < CATCHUP_BATCH {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// The `publication` half of [`ChangefeedReader::drain`]: each record goes to
+/// every subscriber following the topic its `out` names.
+async fn drain_publications(
+    db: &Surreal<any::Any>,
+    subscribers: &mut HashMap<String, Subscriber>,
+    tail: &AtomicI64,
+) -> Result<()> {
+    let Some(mut since) = subscribers
+        .values()
+        .flat_map(|s| s.topics.iter().map(|t| t.position))
+        .min()
+    else {
+        return Ok(());
+    };
+
+    loop {
+        let Some(page) = read_page(db, PUBLICATION_TABLE, since)
+            .await
+            .map_err(|source| {
+                let followers = subscribers.iter().filter(|(_, s)| !s.topics.is_empty());
+                scan_error(followers.map(|(name, _)| name), source)
+            })?
+        else {
+            break;
+        };
+
+        let read_cursor = page.read_cursor;
+        for s in subscribers.values_mut() {
+            for t in s.topics.iter_mut() {
+                if t.position >= read_cursor {
+                    continue;
+                }
+                let changes: Vec<_> = page
+                    .records
+                    .iter()
+                    .filter(|(vs, record)| recipient_of(record) == Some(&t.id) && t.position <= *vs)
+                    .cloned()
+                    .collect();
+                if !changes.is_empty() {
+                    let _ = s.tx.send(ReaderEvent::Batch(Batch {
+                        changes,
+                        read_cursor,
+                        topic: Some(t.name.clone()),
+                    }));
+                }
+                t.position = read_cursor;
+            }
+        }
+        tail.fetch_max(read_cursor, Ordering::AcqRel);
+
+        since = read_cursor;
+        if page.changesets < CATCHUP_BATCH {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// One `SHOW CHANGES` page.
+struct Page {
+    /// `(versionstamp, record)` for every created/updated edge, in log order.
+    records: Vec<(i64, Value)>,
+    /// Changesets read; fewer than [`CATCHUP_BATCH`] means the live edge.
+    changesets: usize,
+    /// Next unread versionstamp after this page (`max_seen + 1`).
+    read_cursor: i64,
+}
+
+/// Read one page of `table`'s changefeed from `since`, or `None` at the live
+/// edge.
+async fn read_page(
+    db: &Surreal<any::Any>,
+    table: &str,
+    since: i64,
+) -> std::result::Result<Option<Page>, surrealdb::Error> {
+    let q = format!("SHOW CHANGES FOR TABLE {table} SINCE {since} LIMIT {CATCHUP_BATCH}");
+    let v: Value = db.query(&q).await?.take(0)?;
+    let Value::Array(changesets) = v else {
+        return Ok(None);
+    };
+    if changesets.is_empty() {
+        return Ok(None);
+    }
+
+    let mut max_vs = since - 1;
+    let mut records = Vec::new();
+    for changeset in changesets.iter() {
+        let Value::Object(obj) = changeset else {
+            continue;
+        };
+        let vs = versionstamp_of(changeset).unwrap_or(since);
+        max_vs = max_vs.max(vs);
+        let Some(Value::Array(changes)) = obj.get("changes") else {
+            continue;
+        };
+        for change in changes.iter() {
+            let Value::Object(op) = change else { continue };
+            // RELATE-created edges surface as a "create"/"update" op carrying
+            // the full record; "delete" ops (retention sweep) are skipped.
+            if let Some(record) = op.get("update").or_else(|| op.get("create")) {
+                records.push((vs, record.clone()));
+            }
+        }
+    }
+    Ok(Some(Page {
+        records,
+        changesets: changesets.len(),
+        read_cursor: max_vs + 1,
+    }))
+}
+
+/// [`Error::Changefeed`] for a scan read on behalf of `agents`.
+fn scan_error<'a>(agents: impl Iterator<Item = &'a String>, source: surrealdb::Error) -> Error {
+    let mut agents: Vec<&str> = agents.map(String::as_str).collect();
+    agents.sort_unstable();
+    Error::Changefeed {
+        agents: agents.join(", "),
+        source,
+    }
+}
+
+/// Register the coalition's LIVE wake-up: one `LIVE SELECT` per feed, merged
+/// into a single stream, so a publish wakes the reader just like a direct
+/// message. The payload is ignored (typed as `Value` so it can never fail to
+/// deserialize) — it only signals "something changed"; delivery happens via
+/// the scan. Unfiltered on purpose: membership and subscriptions change at
+/// runtime, and a wake for another coalition's traffic costs one scan that
+/// hands nobody anything.
+pub(crate) async fn subscribe(db: &Surreal<any::Any>) -> Result<WakeStream> {
+    let query =
+        format!("LIVE SELECT id FROM {MESSAGE_TABLE}; LIVE SELECT id FROM {PUBLICATION_TABLE};");
+    let mut response = db.query(query).await.map_err(Error::WakeUp)?;
+    let messages = response
+        .stream::<Notification<Value>>(0)
+        .map_err(Error::WakeUp)?;
+    let publications = response
+        .stream::<Notification<Value>>(1)
+        .map_err(Error::WakeUp)?;
+    Ok(futures::stream::select(messages, publications).boxed())
+}
+
+/// Oldest versionstamp retained in `table`'s changefeed, or 0 when it is
+/// empty: a cursor there replays everything the feed still holds.
+pub(crate) async fn oldest_versionstamp(
+    db: &Surreal<any::Any>,
+    table: &str,
+) -> std::result::Result<i64, surrealdb::Error> {
+    let q = format!("SHOW CHANGES FOR TABLE {table} SINCE 0 LIMIT 1");
+    let v: Value = db.query(&q).await?.take(0)?;
+    Ok(max_versionstamp(&v))
+}
+
+/// The coalition's reader task: scan on every LIVE wake and every
+/// [`ChangefeedReader::register`], until `token` cancels. `stream` is the
+/// wake-up [`subscribe`]d by `Coalition::new`, so a subscription failure at
+/// startup surfaces there; later stream errors resubscribe with capped
+/// exponential backoff and rescan, so nothing published meanwhile is missed.
+/// A [`ConnectionEvent::Reconnected`] from `client`'s supervisor resubscribes
+/// on the new session straight away and rescans from every agent's position.
+///
+/// Library-side lifecycle primitive, like the listen loops it feeds.
+pub(crate) async fn reader_loop(
+    reader: Arc<ChangefeedReader>,
+    client: Client,
+    stream: WakeStream,
+    token: CancellationToken,
+) {
+    let mut events = client.events();
+    let mut stream = Some(stream);
+    let mut backoff = RECONNECT_BACKOFF_START;
+
+    loop {
+        // The current session: a reconnect swaps it, and the loop below
+        // breaks out so the next pass picks up the new one.
+        let db = &client.db();
+        let mut live = match stream.take() {
+            Some(s) => s,
+            None => match subscribe(db).await {
+                Ok(s) => s,
+                Err(e) => {
+                    tracing::error!("resubscribe failed: {e}");
+                    if cancel_or_sleep(&token, backoff).await {
+                        return;
+                    }
+                    backoff = next_backoff(backoff);
+                    continue;
+                }
+            },
+        };
+
+        // Scan up to the live edge after every (re)subscribe. Anything
+        // published during the scan is buffered by the live stream and
+        // handled by the next scan below.
+        if let Err(e) = reader.drain(db).await {
+            tracing::error!("changefeed scan failed: {e}");
+            drop(live);
+            if cancel_or_sleep(&token, backoff).await {
+                return;
+            }
+            backoff = next_backoff(backoff);
+            continue;
+        }
+        backoff = RECONNECT_BACKOFF_START;
+
+        // Each wake or registration triggers a scan; stream end/error breaks
+        // out to reconnect, and a replaced session resubscribes at once.
+        let mut reconnected = false;
+        loop {
+            tokio::select! {
+                // `biased`: cancellation takes strict priority so a hot
+                // stream can't starve shutdown.
+                biased;
+                _ = token.cancelled() => {
+                    tracing::info!("changefeed reader received shutdown");
+                    return;
+                }
+                event = events.recv() => {
+                    if let Ok(ConnectionEvent::Reconnected) = event {
+                        tracing::info!("connection re-established, resubscribing");
+                        reconnected = true;
+                        break;
+                    }
+                }
+                _ = reader.wake.notified() => {
+                    if let Err(e) = reader.drain(db).await {
+                        tracing::error!("changefeed scan failed: {e}");
+                        break;
+                    }
+                }
+                maybe = live.next() => match maybe {
+                    Some(Ok(_wake)) => {
+                        if let Err(e) = reader.drain(db).await {
+                            tracing::error!("changefeed scan failed: {e}");
+                            break;
+                        }
+                    }
+                    Some(Err(error)) => {
+                        tracing::error!("live wake stream error: {error}");
+                        break;
+                    }
+                    None => {
+                        tracing::info!("live wake stream ended, reconnecting");
+                        break;
+                    }
+                }
+            }
+        }
+
+        drop(live);
+        if reconnected {
+            backoff = RECONNECT_BACKOFF_START;
+            continue;
+        }
+        if cancel_or_sleep(&token, backoff).await {
+            return;
+        }
+        backoff = next_backoff(backoff);
+    }
+}
+
+/// Sleep for `dur` unless cancelled first. Returns `true` if cancellation won
+/// (the caller should stop).
+async fn cancel_or_sleep(token: &CancellationToken, dur: Duration) -> bool {
+    tokio::select! {
+        _ = token.cancelled() => true,
+        _ = sleep(dur) => false,
+    }
+}
+
+/// Capped exponential backoff step.
+fn next_backoff(current: Duration) -> Duration {
+    (current * 2).min(RECONNECT_BACKOFF_MAX)
+}
+
+/// Pull the `versionstamp` out of a `cursor` record (or a `SHOW CHANGES`
+/// changeset) `Value::Object`.
+pub(crate) fn versionstamp_of(v: &Value) -> Option<i64> {
+    match v {
+        Value::Object(o) => match o.get("versionstamp") {
+            Some(Value::Number(n)) => n.to_int(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Largest `versionstamp` in a `SHOW CHANGES` result array (0 if empty/none).
+pub(crate) fn max_versionstamp(v: &Value) -> i64 {
+    let Value::Array(arr) = v else { return 0 };
+    arr.iter().filter_map(versionstamp_of).max().unwrap_or(0)
+}
+
+/// The `out` of a `message` (recipient) or `publication` (topic) edge record.
+fn recipient_of(record: &Value) -> Option<&RecordId> {
+    let Value::Object(obj) = record else {
+        return None;
+    };
+    match obj.get("out") {
+        Some(Value::RecordId(id)) => Some(id),
+        _ => None,
+    }
+}
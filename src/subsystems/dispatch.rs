@@ -0,0 +1,140 @@
+//! Managed dispatch: register an [`AgentHandler`] for an agent and let the
+//! coalition run its receive loop.
+//!
+//! [`Coalition::register_handler`](crate::subsystems::agents::Coalition::register_handler)
+//! claims the agent's own inbox (see `Coalition::inbox_for`) and spawns a
+//! dispatch loop under the coalition's `TaskTracker`, on a child of the agent's
+//! token. Each delivery runs the handler in its own task, at most
+//! `concurrency` at a time; a saturated handler pool stops pulling from the
+//! inbox, so backpressure reaches that agent's listen loop and no further. A
+//! handler returning `Ok` acks the delivery and an `Err` nacks it — both no-ops
+//! under `AckMode::Auto`. Shutdown is the coalition's usual cancel → close →
+//! wait: the listen loop stops feeding the inbox, the dispatch loop handles
+//! what is still queued there, and in-flight handlers are drained.
+
+use std::future::Future;
+use std::sync::Arc;
+
+use kanal::AsyncReceiver;
+use surrealdb_types::SurrealValue;
+use tokio::sync::Semaphore;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::Instrument;
+
+use crate::error::Result;
+use crate::message::Message;
+use crate::subsystems::agents::{Agent, Delivery};
+
+/// Error type a handler may fail with. Both this crate's `Error` and
+/// `anyhow::Error` convert into it with `?`.
+pub type HandlerError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Per-agent message handler run by the coalition's dispatch loop.
+///
+/// Implement with a plain `async fn handle(...)`; the returned future must be
+/// `Send` because each delivery is handled on its own task.
+pub trait AgentHandler<T: SurrealValue>: Send + Sync + 'static {
+    fn handle(
+        &self,
+        ctx: AgentContext,
+        delivery: Delivery<T>,
+    ) -> impl Future<Output = std::result::Result<(), HandlerError>> + Send;
+}
+
+/// What a handler gets besides the delivery: the agent it runs as, for
+/// sending and replying, plus a token that fires when the agent leaves or the
+/// coalition shuts down.
+#[derive(Debug, Clone)]
+pub struct AgentContext {
+    agent: Agent,
+    token: CancellationToken,
+}
+
+impl AgentContext {
+    /// The agent this handler is registered for.
+    pub fn agent(&self) -> &Agent {
+        &self.agent
+    }
+
+    /// Cancelled when the agent is removed or the coalition shuts down. A
+    /// long-running handler can select on it to finish early.
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    /// [`Agent::send`] as this agent.
+    pub async fn send<P>(&self, to: &str, payload: P) -> Result<()>
+    where
+        P: SurrealValue + Send + Sync + Unpin + 'static,
+    {
+        self.agent.send(to, payload).await
+    }
+
+    /// [`Agent::reply`] as this agent.
+    pub async fn reply<Req, Resp>(&self, request: &Message<Req>, payload: Resp) -> Result<()>
+    where
+        Req: SurrealValue,
+        Resp: SurrealValue + Send + Sync + Unpin + 'static,
+    {
+        self.agent.reply(request, payload).await
+    }
+}
+
+/// Pull from `inbox` and run `handler` on each delivery, at most
+/// `concurrency` at once, until the inbox closes. Returns only after every
+/// handler it started has finished.
+///
+/// `token` (a child of the agent's) only reaches handlers through
+/// [`AgentContext::cancellation_token`]; the loop itself runs until the
+/// agent's listen loop, cancelled by the same token, exits and drops the
+/// inbox sender. Under `AckMode::Auto` a queued delivery's cursor is already
+/// committed, so stopping at the cancel would lose it for good.
+pub(crate) async fn dispatch_loop<T, H>(
+    handler: Arc<H>,
+    agent: Agent,
+    inbox: AsyncReceiver<Delivery<T>>,
+    concurrency: usize,
+    task_tracker: TaskTracker,
+    token: CancellationToken,
+) where
+    T: SurrealValue + Send + Sync + Unpin + 'static,
+    H: AgentHandler<T>,
+{
+    let permits = u32::try_from(concurrency.max(1)).unwrap_or(u32::MAX);
+    let limit = Arc::new(Semaphore::new(permits as usize));
+    let ctx = AgentContext { agent, token };
+
+    loop {
+        // Take a permit before pulling, so a saturated pool leaves deliveries
+        // in the inbox (backpressuring the listen loop) instead of queueing.
+        let permit = limit
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("dispatch semaphore is never closed");
+        let Ok(delivery) = inbox.recv().await else {
+            break; // agent's listen loop is gone and the inbox is drained
+        };
+
+        let handler = handler.clone();
+        let ctx = ctx.clone();
+        let span = tracing::info_span!("handler", attempt = delivery.attempt);
+        task_tracker.spawn(
+            async move {
+                let acker = delivery.acker();
+                match handler.handle(ctx, delivery).await {
+                    Ok(()) => acker.ack(),
+                    Err(e) => {
+                        tracing::warn!("handler failed: {e}");
+                        acker.nack();
+                    }
+                }
+                drop(permit);
+            }
+            .instrument(span),
+        );
+    }
+
+    // Every permit back means no handler is still running.
+    let _ = limit.acquire_many(permits).await;
+}
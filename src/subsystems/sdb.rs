@@ -1,90 +1,115 @@
+use crate::client::Client;
 use crate::error::{Error, Result};
+use crate::migrate;
 use crate::sdb_server::SurrealDBContainer;
-use crate::settings::SETTINGS;
-use std::sync::OnceLock;
+use crate::settings::{Sdb, Settings};
+use std::sync::{Arc, OnceLock};
 use surrealdb::Surreal;
 use surrealdb::engine::any;
-use surrealdb::opt::auth::Root;
-use tokio::sync::{OnceCell, watch};
+use tokio::sync::{OnceCell, oneshot, watch};
 use tokio::time::{Duration, sleep};
 use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
 
-static DB_READY: OnceLock<watch::Sender<bool>> = OnceLock::new();
+/// Process-wide readiness: `None` until [`sdb_task`] finishes startup, then
+/// `Ok` or the startup error, shared because errors are not `Clone`.
+type Readiness = Option<std::result::Result<(), Arc<Error>>>;
+
+static DB_READY: OnceLock<watch::Sender<Readiness>> = OnceLock::new();
 
 pub struct SurrealDBWrapper;
 
+/// The process-wide client; see [`SurrealDBWrapper::client`].
+static CLIENT: OnceCell<Client> = OnceCell::const_new();
+
 impl SurrealDBWrapper {
-    fn init_ready_channel() -> &'static watch::Sender<bool> {
+    fn init_ready_channel() -> &'static watch::Sender<Readiness> {
         DB_READY.get_or_init(|| {
-            let (tx, _rx) = watch::channel(false);
+            let (tx, _rx) = watch::channel(None);
             tx
         })
     }
 
-    fn get_ready_receiver() -> watch::Receiver<bool> {
+    fn get_ready_receiver() -> watch::Receiver<Readiness> {
         Self::init_ready_channel().subscribe()
     }
 
     /// Wait for the database to be ready (blocking async function).
     /// This can be called from multiple places concurrently.
+    ///
+    /// Returns [`Error::StartupFailed`] wrapping [`sdb_task`]'s own typed
+    /// error (e.g. [`Error::Connect`], [`Error::Auth`], [`Error::Schema`]) if
+    /// it could not start the container or establish the connection.
     pub async fn wait_until_ready() -> Result<()> {
         let mut rx = Self::get_ready_receiver();
-        rx.wait_for(|&ready| ready)
+        let state = rx
+            .wait_for(Option::is_some)
             .await
             .map_err(|_| Error::ReadyChannelClosed)?;
-        Ok(())
+        match state.as_ref() {
+            Some(Err(error)) => Err(Error::StartupFailed(Arc::clone(error))),
+            _ => Ok(()),
+        }
     }
 
     fn set_ready() {
         let tx = Self::init_ready_channel();
-        let _ = tx.send(true);
+        let _ = tx.send(Some(Ok(())));
     }
 
-    pub async fn connection() -> &'static Surreal<any::Any> {
-        static CONNECTION: OnceCell<Surreal<any::Any>> = OnceCell::const_new();
+    fn set_failed(error: Arc<Error>) {
+        let tx = Self::init_ready_channel();
+        let _ = tx.send(Some(Err(error)));
+    }
 
-        CONNECTION
-            .get_or_init(|| async {
-                tracing::debug!("Initializing SurrealDB connection");
-                SurrealDBWrapper::client().await
-            })
-            .await
+    /// The process-wide connection, built from `SETTINGS` on first use — the
+    /// current session of [`Client::global`], so one taken after a reconnect
+    /// is the new session. To talk to another database (or several), use a
+    /// [`Client`] instead.
+    pub async fn connection() -> Result<Surreal<any::Any>> {
+        Ok(Self::client().await?.db())
     }
 
-    async fn client() -> Surreal<any::Any> {
-        let db = any::connect(&SETTINGS.sdb.endpoint)
-            .await
-            .map_err(Error::Connect)
-            .expect("Failed to connect to SurrealDB via WebSocket");
+    /// Whether the process-wide database answers queries; see
+    /// [`Client::check_ready`]. Each call opens and signs in a fresh session
+    /// (except on an embedded engine), so a daemon using it as a liveness
+    /// check should run it every few seconds, not per operation.
+    pub async fn probe() -> Result<()> {
+        Self::client().await?.check_ready().await
+    }
 
-        db.use_ns(&SETTINGS.sdb.namespace)
-            .use_db(&SETTINGS.sdb.database)
+    /// The process-wide [`Client`] behind [`SurrealDBWrapper::connection`].
+    ///
+    /// Defines the schema exactly once, after signin and before any
+    /// agent/message ops: the `OnceCell` keeps the first [`Client::connect`]
+    /// that succeeds. A failed setup is returned and leaves the cell empty, so
+    /// the next call tries again. `IF NOT EXISTS` on every statement keeps
+    /// repeated startups (e.g. reconnects against a persistent store)
+    /// idempotent.
+    pub(crate) async fn client() -> Result<&'static Client> {
+        CLIENT
+            .get_or_try_init(|| async {
+                tracing::debug!("Initializing SurrealDB connection");
+                Client::connect(Settings::global()?.clone()).await
+            })
             .await
-            .map_err(Error::UseNsDb)
-            .expect("Failed to select namespace and database");
+    }
 
-        let _ = db
-            .signin(Root {
-                username: SETTINGS.sdb.username.clone(),
-                password: SETTINGS.sdb.password.clone(),
+    /// [`SurrealDBWrapper::client`], connecting with `settings` if it is not
+    /// set up yet: [`sdb_task`]'s, whose endpoint may name a port Docker
+    /// picked (`docker.dynamic_port`). Anyone else should wait for
+    /// [`SurrealDBWrapper::wait_until_ready`] before the first connection.
+    async fn client_for(settings: Settings) -> Result<&'static Client> {
+        CLIENT
+            .get_or_try_init(|| async {
+                tracing::debug!("Initializing SurrealDB connection");
+                Client::connect(settings).await
             })
             .await
-            .map_err(Error::Auth)
-            .expect("Failed to authenticate with SurrealDB");
-
-        // Define schema exactly once, after signin and before any agent/message
-        // ops. Every caller reaches the database through `connection()`, which
-        // runs `client()` exactly once via its `OnceCell`, so this also runs
-        // exactly once. `IF NOT EXISTS` on every statement keeps repeated
-        // startups (e.g. reconnects against a persistent store) idempotent.
-        Self::define_schema(&db)
-            .await
-            .expect("Failed to define SurrealDB schema");
-
-        db
     }
 
-    /// Define the `agent` and `message` schema idempotently.
+    /// Bring the schema up to date through [`crate::migrate`] (or, without
+    /// `sdb.auto_migrate`, log what is pending).
     ///
     /// `agent` is SCHEMAFULL: it only ever holds `name`/`created`, so locking
     /// the shape catches typos and stray fields.
@@ -106,33 +131,30 @@ impl SurrealDBWrapper {
     /// feed is set via `DEFINE TABLE IF NOT EXISTS` so it is established once at
     /// table creation and **preserved across restarts** — re-defining it every
     /// startup would reset the change log and defeat cross-restart catch-up.
-    /// NOTE: a `message` table created by a pre-changefeed schema version will
-    /// not gain the feed retroactively (this lib is pre-1.0; no migration ships).
+    /// A missing or outdated window is fixed with `ALTER TABLE` by the
+    /// migration run instead (see [`crate::migrate`]).
     ///
     /// `cursor` persists each agent's high-water mark (the last versionstamp it
     /// drained) so catch-up is bounded and exactly resumable across restarts.
-    async fn define_schema(db: &Surreal<any::Any>) -> Result<()> {
-        let retention = SETTINGS.sdb.message_retention_secs;
-        let schema = format!(
-            "
-            DEFINE TABLE IF NOT EXISTS agent SCHEMAFULL;
-            DEFINE FIELD IF NOT EXISTS name ON agent TYPE string;
-            DEFINE FIELD IF NOT EXISTS created ON agent TYPE datetime;
-
-            DEFINE TABLE IF NOT EXISTS message TYPE RELATION IN agent OUT agent SCHEMALESS CHANGEFEED {retention}s;
-            DEFINE FIELD IF NOT EXISTS created ON message TYPE datetime;
-
-            DEFINE TABLE IF NOT EXISTS cursor SCHEMAFULL;
-            DEFINE FIELD IF NOT EXISTS versionstamp ON cursor TYPE int;
-        "
-        );
-
-        db.query(&schema)
-            .await
-            .map_err(Error::Schema)?
-            .check()
-            .map_err(Error::Schema)?;
-
+    ///
+    /// Topics mirror the same model: `publication` (agent → topic) is a second
+    /// changefeed-backed log with the same retention, and each `subscribes`
+    /// edge (agent → topic) holds that subscription's cursor. See
+    /// [`crate::topic`].
+    ///
+    /// With `sdb.secured` the migrations also define the agent access method
+    /// and row-level permissions (see [`crate::migrate`]).
+    pub(crate) async fn define_schema(db: &Surreal<any::Any>, sdb: &Sdb) -> Result<()> {
+        if sdb.auto_migrate {
+            migrate::apply_to(db, sdb).await?;
+        } else {
+            let plan = migrate::plan_for(db, sdb).await?;
+            if !plan.is_current() {
+                for step in &plan.steps {
+                    tracing::warn!("schema: pending {step}; run migrate::apply");
+                }
+            }
+        }
         Ok(())
     }
 }
@@ -143,42 +165,142 @@ impl SurrealDBWrapper {
 /// their own top-level shutdown (`tokio::signal::ctrl_c`, parent
 /// CancellationToken, test harness, etc.) and cancel the token when ready.
 ///
-/// On cancel: drains for 2s to let outstanding queries complete, then stops
-/// the local container (if present).
+/// Three connection modes, chosen from settings:
+/// - embedded (`sdb.endpoint` is `mem://`, `surrealkv://…`, `rocksdb://…`):
+///   SurrealDB runs in-process; no Docker is touched.
+/// - production (`environment = "production"`): connects to the remote
+///   endpoint as-is.
+/// - otherwise: starts a local SurrealDB container via bollard.
+///
+/// Uses `SETTINGS` and the process-wide connection, and signals
+/// [`SurrealDBWrapper::wait_until_ready`]; see [`sdb_task_with_settings`] for
+/// an explicit configuration. Settings that fail to load or validate are a
+/// startup failure ([`Error::Config`]), not a panic. A startup failure is
+/// returned as [`Error::StartupFailed`], sharing the typed error that
+/// `wait_until_ready` reports.
+///
+/// Once ready it supervises the connection until cancelled, replacing a dead
+/// session (see [`Client::supervise`]). On cancel: drains for 2s to let
+/// outstanding queries complete, then stops the local container (if present).
 pub async fn sdb_task(token: CancellationToken) -> Result<()> {
     tracing::info!("sdb task starting.");
-    let container = if SETTINGS.environment == "production" {
-        tracing::info!("sdb using cloud connection.");
-        None
-    } else {
-        tracing::info!("sdb using local container.");
-        let container = SurrealDBContainer::new()
-            .await
-            .expect("Failed to create SurrealDB container");
-        container
-            .start_and_wait()
-            .await
-            .expect("Failed to start and wait for container");
-        Some(container)
+    let tasks = TaskTracker::new();
+    let started = async {
+        let settings = Settings::global()?;
+        let container = start_container(settings, &tasks, &token).await?;
+        let settings = container
+            .as_ref()
+            .map_or(settings, SurrealDBContainer::settings)
+            .clone();
+        // Establish the initial connection (works for embedded, production and local)
+        match SurrealDBWrapper::client_for(settings).await {
+            Ok(client) => Ok((container, client)),
+            Err(e) => {
+                stop_container(container, &tasks).await;
+                Err(e)
+            }
+        }
+    };
+    let (container, client) = match started.await {
+        Ok(started) => started,
+        Err(e) => {
+            tracing::error!("sdb startup failed: {e}");
+            let e = Arc::new(e);
+            SurrealDBWrapper::set_failed(Arc::clone(&e));
+            return Err(Error::StartupFailed(e));
+        }
     };
-
-    // Establish the initial connection (works for both production and local)
-    let _db = SurrealDBWrapper::connection().await.to_owned();
 
     // Signal database is ready
     SurrealDBWrapper::set_ready();
 
     tracing::info!("sdb ready and accepting connections.");
+    stop_on_cancel(token, container, client, &tasks).await
+}
+
+/// [`sdb_task`] for an explicit `settings`: same three modes and shutdown,
+/// but instead of the process-wide connection it builds its own [`Client`]
+/// and hands it over on `ready_tx` once the database accepts connections.
+/// Run one per tenant to host several databases in one process.
+///
+/// A startup failure is returned, dropping `ready_tx`.
+pub async fn sdb_task_with_settings(
+    token: CancellationToken,
+    settings: Settings,
+    ready_tx: oneshot::Sender<Client>,
+) -> Result<()> {
+    tracing::info!("sdb task starting for {}.", settings.sdb.database);
+    let tasks = TaskTracker::new();
+    let container = start_container(&settings, &tasks, &token).await?;
+    let settings = match &container {
+        Some(container) => container.settings().clone(),
+        None => settings,
+    };
+
+    let client = match Client::connect(settings).await {
+        Ok(client) => client,
+        Err(e) => {
+            stop_container(container, &tasks).await;
+            return Err(e);
+        }
+    };
+    let _ = ready_tx.send(client.clone());
+
+    tracing::info!("sdb ready and accepting connections.");
+    stop_on_cancel(token, container, &client, &tasks).await
+}
+
+/// Start a local container unless `settings` select an embedded engine or
+/// production. A container that fails its startup is stopped again. Its
+/// background tasks run on `tasks` under `token`; see
+/// [`SurrealDBContainer::new`].
+async fn start_container(
+    settings: &Settings,
+    tasks: &TaskTracker,
+    token: &CancellationToken,
+) -> Result<Option<SurrealDBContainer>> {
+    if settings.sdb.is_embedded() {
+        tracing::info!("sdb using embedded engine at {}.", settings.sdb.endpoint);
+        return Ok(None);
+    }
+    if settings.environment == "production" {
+        tracing::info!("sdb using cloud connection.");
+        return Ok(None);
+    }
+    tracing::info!("sdb using local container.");
+    let mut container = SurrealDBContainer::new(settings.clone(), tasks, token).await?;
+    if let Err(e) = container.start_and_wait().await {
+        stop_container(Some(container), tasks).await;
+        return Err(e);
+    }
+    Ok(Some(container))
+}
+
+/// Stop `container` (if any), logging rather than returning a failure, then
+/// wait for its background tasks on `tasks` to finish.
+async fn stop_container(container: Option<SurrealDBContainer>, tasks: &TaskTracker) {
+    if let Some(container) = container
+        && let Err(e) = container.stop().await
+    {
+        tracing::warn!("failed to stop SurrealDB container: {e}");
+    }
+    tasks.close();
+    tasks.wait().await;
+}
 
-    token.cancelled().await;
+/// Supervise `client`'s session (see [`Client::supervise`]) until `token`
+/// cancels, drain for 2s, then stop `container` (if any) and wait for
+/// `tasks`.
+async fn stop_on_cancel(
+    token: CancellationToken,
+    container: Option<SurrealDBContainer>,
+    client: &Client,
+    tasks: &TaskTracker,
+) -> Result<()> {
+    client.supervise(token).await;
     tracing::info!("sdb shutting down ...");
     sleep(Duration::from_secs(2)).await;
-    if let Some(container) = container {
-        container
-            .stop()
-            .await
-            .expect("Failed to stop SurrealDB container");
-    }
+    stop_container(container, tasks).await;
     tracing::info!("sdb stopped.");
     Ok(())
 }
@@ -0,0 +1,169 @@
+//! Topic publish/subscribe on the durable bus.
+//!
+//! [`Agent::publish`](crate::subsystems::agents::Agent::publish) creates one
+//! `agent->publication->topic` edge, however many subscribers the topic has,
+//! so a broadcast is a single atomic write. `publication` carries its own
+//! `CHANGEFEED`, read by the coalition's changefeed reader alongside
+//! `message`.
+//!
+//! A subscription is an `agent->subscribes->topic` edge holding that
+//! subscriber's cursor for the topic in `versionstamp` — the same per-subscriber
+//! cursor model as `cursor:<agent>`, and discoverable with a graph query
+//! (`SELECT <-subscribes<-agent FROM topic:<name>`). Where a new subscription
+//! starts is a [`SubscribeFrom`]: by default at the oldest publication the
+//! changefeed still retains, so a late subscriber replays the topic's history
+//! within the retention window; with [`SubscribeFrom::Latest`] just past the
+//! latest publication. Either way it resumes from its cursor after a restart.
+//!
+//! Topic deliveries follow the coalition's `AckMode` like direct messages:
+//! under `AckMode::Manual` a subscription's cursor only moves past
+//! acknowledged publications, and unacknowledged ones are redelivered (see
+//! [`crate::ack`]).
+
+use surrealdb::Surreal;
+use surrealdb::engine::any;
+use surrealdb_types::{RecordId, SurrealValue, Value};
+
+use crate::error::{Error, Result};
+use crate::subsystems::agents::AGENT_TABLE;
+use crate::subsystems::changefeed::{self, ChangefeedReader};
+
+pub const TOPIC_TABLE: &str = "topic";
+pub const PUBLICATION_TABLE: &str = "publication";
+pub const SUBSCRIBES_TABLE: &str = "subscribes";
+
+/// Topic names follow the agent-name rule (non-empty, ASCII alphanumeric or
+/// underscore): they are record-id keys too.
+pub(crate) fn validate(topic: &str) -> Result<()> {
+    if topic.is_empty() || !topic.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(Error::InvalidTopicName {
+            name: topic.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Where a new subscription's cursor starts in the `publication` feed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SubscribeFrom {
+    /// The oldest publication still retained (`sdb.message_retention_secs`):
+    /// a late subscriber replays the topic's retained history.
+    #[default]
+    Earliest,
+    /// Just past the latest publication: only what is published from now on.
+    Latest,
+}
+
+/// One persisted subscription: the topic and the subscriber's cursor in it.
+#[derive(Debug, SurrealValue)]
+pub(crate) struct Subscription {
+    pub(crate) topic: String,
+    pub(crate) versionstamp: i64,
+}
+
+/// Every topic `agent` subscribes to, with its cursor.
+pub(crate) async fn load(db: &Surreal<any::Any>, agent: &str) -> Result<Vec<Subscription>> {
+    db.query("SELECT out.name AS topic, versionstamp FROM subscribes WHERE in = $agent")
+        .bind(("agent", RecordId::new(AGENT_TABLE, agent)))
+        .await
+        .map_err(|source| Error::CursorLoad {
+            agent: agent.to_string(),
+            source,
+        })?
+        .take(0)
+        .map_err(|source| Error::CursorLoad {
+            agent: agent.to_string(),
+            source,
+        })
+}
+
+/// Subscribe `agent` to `topic` (creating the topic if needed) with its
+/// cursor where `from` says. Returns that cursor, or `None` if it was already
+/// subscribed.
+pub(crate) async fn create(
+    db: &Surreal<any::Any>,
+    reader: &ChangefeedReader,
+    agent: &str,
+    topic: &str,
+    from: SubscribeFrom,
+) -> Result<Option<i64>> {
+    let subscribe_err = |source| Error::Subscribe {
+        agent: agent.to_string(),
+        topic: topic.to_string(),
+        source,
+    };
+    let agent_id = RecordId::new(AGENT_TABLE, agent);
+    let topic_id = RecordId::new(TOPIC_TABLE, topic);
+
+    let existing: Option<Value> = db
+        .query("SELECT VALUE id FROM ONLY subscribes WHERE in = $agent AND out = $topic LIMIT 1")
+        .bind(("agent", agent_id.clone()))
+        .bind(("topic", topic_id.clone()))
+        .await
+        .map_err(subscribe_err)?
+        .take(0)
+        .map_err(subscribe_err)?;
+    if existing.is_some() {
+        return Ok(None);
+    }
+
+    let start = match from {
+        SubscribeFrom::Earliest => changefeed::oldest_versionstamp(db, PUBLICATION_TABLE).await,
+        SubscribeFrom::Latest => reader.next_versionstamp(db, PUBLICATION_TABLE).await,
+    }
+    .map_err(subscribe_err)?;
+    db.query(
+        "UPSERT $topic SET name = $name; \
+         RELATE $agent->subscribes->$topic SET versionstamp = $start;",
+    )
+    .bind(("topic", topic_id))
+    .bind(("name", topic.to_string()))
+    .bind(("agent", agent_id))
+    .bind(("start", start))
+    .await
+    .map_err(subscribe_err)?
+    .check()
+    .map_err(subscribe_err)?;
+    Ok(Some(start))
+}
+
+/// Drop `agent`'s subscription to `topic`, if any.
+pub(crate) async fn delete(db: &Surreal<any::Any>, agent: &str, topic: &str) -> Result<()> {
+    let subscribe_err = |source| Error::Subscribe {
+        agent: agent.to_string(),
+        topic: topic.to_string(),
+        source,
+    };
+    db.query("DELETE subscribes WHERE in = $agent AND out = $topic")
+        .bind(("agent", RecordId::new(AGENT_TABLE, agent)))
+        .bind(("topic", RecordId::new(TOPIC_TABLE, topic)))
+        .await
+        .map_err(subscribe_err)?
+        .check()
+        .map_err(subscribe_err)?;
+    Ok(())
+}
+
+/// Persist `agent`'s cursor in `topic`.
+pub(crate) async fn save_cursor(
+    db: &Surreal<any::Any>,
+    agent: &str,
+    topic: &str,
+    versionstamp: i64,
+) -> Result<()> {
+    let save_err = |source| Error::CursorSave {
+        agent: agent.to_string(),
+        source,
+    };
+    db.query(
+        "UPDATE subscribes SET versionstamp = $versionstamp WHERE in = $agent AND out = $topic",
+    )
+    .bind(("versionstamp", versionstamp))
+    .bind(("agent", RecordId::new(AGENT_TABLE, agent)))
+    .bind(("topic", RecordId::new(TOPIC_TABLE, topic)))
+    .await
+    .map_err(save_err)?
+    .check()
+    .map_err(save_err)?;
+    Ok(())
+}
@@ -1,12 +1,24 @@
+use kanal::AsyncReceiver;
 use serde::{Deserialize, Serialize};
 use surrealdb::Surreal;
 use surrealdb::engine::any;
 use surrealdb::opt::Resource;
+use surrealdb_live_message::ack::AckMode;
+use surrealdb_live_message::client::Client;
+use surrealdb_live_message::dead_letter::{self, DeadLetter};
 use surrealdb_live_message::error::Error;
+use surrealdb_live_message::identity;
 use surrealdb_live_message::logger;
 use surrealdb_live_message::message::{MESSAGE_TABLE, Message};
-use surrealdb_live_message::subsystems::agents::{AGENT_TABLE, Agent, Coalition, Delivery};
+use surrealdb_live_message::migrate::{self, Step};
+use surrealdb_live_message::sdb_server::SurrealDBContainer;
+use surrealdb_live_message::settings::{Auth, SETTINGS, Settings};
+use surrealdb_live_message::subsystems::agents::{
+    AGENT_TABLE, Agent, Coalition, CoalitionOptions, Delivery,
+};
+use surrealdb_live_message::subsystems::dispatch::{AgentContext, AgentHandler, HandlerError};
 use surrealdb_live_message::subsystems::sdb::{self, SurrealDBWrapper};
+use surrealdb_live_message::topic::{SUBSCRIBES_TABLE, SubscribeFrom};
 use surrealdb_types::{RecordId, SurrealValue};
 use tokio::time::{Duration, timeout};
 use tokio_util::{sync::CancellationToken, sync::DropGuard, task::TaskTracker};
@@ -23,6 +35,7 @@ struct ChatMessage {
 async fn init_db(db: &Surreal<any::Any>) {
     let _ = db.delete(Resource::from(MESSAGE_TABLE)).await;
     let _ = db.delete(Resource::from(AGENT_TABLE)).await;
+    let _ = db.delete(Resource::from(SUBSCRIBES_TABLE)).await;
     let _ = db.create(Resource::from(MESSAGE_TABLE)).await;
     let _ = db.create(Resource::from(AGENT_TABLE)).await;
 }
@@ -36,6 +49,9 @@ async fn init_db(db: &Surreal<any::Any>) {
 ///   `coalition.shutdown().await` when assertions are done
 /// - no `tokio-graceful-shutdown`, no `Toplevel`, no `SIGINT` self-kill,
 ///   no `tokio::join!` over parallel futures
+///
+/// Runs against a Docker container by default; `SDB__ENDPOINT=mem://` runs the
+/// same scenarios against the embedded in-process engine with no Docker.
 #[tokio::test]
 async fn test_agent_messaging() {
     logger::setup();
@@ -46,7 +62,7 @@ async fn test_agent_messaging() {
 
     // Panic-safe cleanup: if any assertion below panics, Drop runs on
     // _drop_guard which cancels the harness token, which cascades to the
-    // sdb_task via child_token() → container shutdown → docker auto_remove.
+    // sdb_task via child_token() → container stop and removal.
     // Without this guard, a panic skips the explicit shutdown at the end of
     // the test and leaves the container running for the next invocation.
     let _drop_guard: DropGuard = harness_token.clone().drop_guard();
@@ -67,8 +83,13 @@ async fn test_agent_messaging() {
     .await
     .expect("timeout waiting for sdb")
     .expect("sdb ready signal failed");
+    SurrealDBWrapper::probe()
+        .await
+        .expect("ready database answers the probe");
 
-    let db = sdb::SurrealDBWrapper::connection().await;
+    let db = &sdb::SurrealDBWrapper::connection()
+        .await
+        .expect("sdb connection");
     init_db(db).await;
 
     // 3) Build the coalition. It owns its own lifecycle internally.
@@ -196,10 +217,12 @@ async fn test_agent_messaging() {
     // Additional scenarios.
     //
     // Run sequentially against the SAME container: `sdb.rs` holds a single
-    // process-global `CONNECTION` OnceCell and `sdb_server.rs` pins a fixed
-    // container name + port, so parallel `#[tokio::test]` fns would collide.
-    // Consolidating keeps one container and a deterministic order (matches the
-    // `rust-v2:testing` "quality over quantity" guidance).
+    // process-global `CLIENT` OnceCell that every `#[tokio::test]` fn in this
+    // binary would share. (Separate runs no longer collide on the container:
+    // `docker.dynamic_port` gives each its own name and port, see
+    // `scenario_parallel_containers`.) Consolidating keeps one container and
+    // a deterministic order (matches the `rust-v2:testing` "quality over
+    // quantity" guidance).
     // ------------------------------------------------------------------
     scenario_unknown_agent(db, &alice).await;
     scenario_schema_enforcement(db).await;
@@ -209,6 +232,21 @@ async fn test_agent_messaging() {
     scenario_invalid_agent_name().await;
     scenario_bus_close().await;
     scenario_durable_restart().await;
+    scenario_manual_ack().await;
+    scenario_request_reply().await;
+    scenario_dynamic_membership().await;
+    scenario_per_agent_inbox().await;
+    scenario_handler_dispatch().await;
+    scenario_shared_reader().await;
+    scenario_dead_letter().await;
+    scenario_topics().await;
+    scenario_topic_manual_ack().await;
+    scenario_explicit_client().await;
+    scenario_connect_failure().await;
+    scenario_database_user(db).await;
+    scenario_secured_identities().await;
+    scenario_migrations().await;
+    scenario_parallel_containers().await;
 
     // 6) Shutdown — coalition first (agent drain), then the sdb task.
     coalition.shutdown().await;
@@ -410,7 +448,7 @@ async fn scenario_backpressure() {
 /// validates names up front, so a name that isn't a safe record-id key (a space
 /// here) fails with [`Error::InvalidAgentName`] before any DB work.
 ///
-/// (This guard plus the parameter-bound LIVE query is why `ListenLoopDropped`
+/// (This guard plus the parameter-bound queries is why `ListenLoopDropped`
 /// no longer has a cheap trigger — like `AgentCreateEmpty`, it is now only
 /// reachable via fault injection.)
 async fn scenario_invalid_agent_name() {
@@ -491,3 +529,1071 @@ async fn scenario_bus_close() {
         .expect("recv did not resolve after shutdown");
     assert!(closed.is_err(), "inbox bus must be closed after shutdown");
 }
+
+/// **Manual ack.** Under [`AckMode::Manual`] the cursor only advances past
+/// acknowledged deliveries: a nack is redelivered at once, an unacked delivery
+/// is replayed after a restart, and an acked one is not.
+async fn scenario_manual_ack() {
+    let options = CoalitionOptions {
+        ack_mode: AckMode::Manual {
+            visibility_timeout: Duration::from_secs(30),
+        },
+        ..CoalitionOptions::default()
+    };
+    let names = || vec!["heidi".to_string(), "ivan".to_string()];
+    let expected = ChatMessage {
+        content: "ack me".to_string(),
+    };
+
+    let c1 = Coalition::<ChatMessage>::new_with_options(names(), options.clone())
+        .await
+        .expect("round-1 coalition");
+    let inbox = c1.inbox();
+    let heidi = c1.agent("heidi").await.expect("heidi in coalition");
+    heidi
+        .send("ivan", expected.clone())
+        .await
+        .expect("heidi → ivan send");
+
+    let d = timeout(Duration::from_secs(5), inbox.recv())
+        .await
+        .expect("first delivery timed out")
+        .expect("inbox bus closed unexpectedly");
+    assert_eq!(d.attempt, 1);
+    d.nack();
+    let d = timeout(Duration::from_secs(5), inbox.recv())
+        .await
+        .expect("nacked delivery was not redelivered")
+        .expect("inbox bus closed unexpectedly");
+    assert_eq!(d.attempt, 2);
+    assert_eq!(d.message.payload, expected);
+    // Crash-equivalent: shut down without acking.
+    c1.shutdown().await;
+
+    let c2 = Coalition::<ChatMessage>::new_with_options(names(), options.clone())
+        .await
+        .expect("round-2 coalition (restart)");
+    let inbox = c2.inbox();
+    let d = timeout(Duration::from_secs(5), inbox.recv())
+        .await
+        .expect("unacked delivery was not replayed on restart")
+        .expect("inbox bus closed unexpectedly");
+    assert_eq!(d.recipient, "ivan");
+    assert_eq!(d.message.payload, expected);
+    d.ack();
+    c2.shutdown().await;
+
+    let c3 = Coalition::<ChatMessage>::new_with_options(names(), options)
+        .await
+        .expect("round-3 coalition (restart)");
+    let inbox = c3.inbox();
+    assert!(
+        timeout(Duration::from_secs(1), inbox.recv()).await.is_err(),
+        "an acked delivery must not be replayed"
+    );
+    c3.shutdown().await;
+}
+
+/// **Request/reply.** `Agent::request` resolves with the responder's
+/// `Agent::reply`, the reply never reaches the shared inbox, a plain message
+/// can't be replied to, and an unanswered request times out.
+async fn scenario_request_reply() {
+    let coalition = Coalition::<ChatMessage>::new(vec!["judy".to_string(), "kim".to_string()])
+        .await
+        .expect("coalition creation");
+    let inbox = coalition.inbox();
+    let judy = coalition.agent("judy").await.expect("judy in coalition");
+    let kim = coalition.agent("kim").await.expect("kim in coalition");
+
+    let responder = tokio::spawn({
+        let inbox = inbox.clone();
+        async move {
+            let d = timeout(Duration::from_secs(5), inbox.recv())
+                .await
+                .expect("request delivery timed out")
+                .expect("inbox bus closed unexpectedly");
+            assert_eq!(d.recipient, "kim");
+            assert!(d.message.correlation_id.is_some());
+            kim.reply(
+                &d.message,
+                ChatMessage {
+                    content: format!("re: {}", d.message.payload.content),
+                },
+            )
+            .await
+            .expect("kim → judy reply");
+            kim
+        }
+    });
+
+    let reply = judy
+        .request::<ChatMessage, ChatMessage>(
+            "kim",
+            ChatMessage {
+                content: "ping".to_string(),
+            },
+            Duration::from_secs(5),
+        )
+        .await
+        .expect("request should be answered");
+    assert_eq!(
+        reply.payload,
+        ChatMessage {
+            content: "re: ping".to_string()
+        }
+    );
+    assert_eq!(reply.r#in, Some(RecordId::new(AGENT_TABLE, "kim")));
+    let kim = responder.await.expect("responder task");
+    assert!(
+        timeout(Duration::from_millis(500), inbox.recv())
+            .await
+            .is_err(),
+        "a routed reply must not reach the shared inbox"
+    );
+
+    // A plain send carries no correlation id, so it can't be replied to.
+    judy.send(
+        "kim",
+        ChatMessage {
+            content: "fire and forget".to_string(),
+        },
+    )
+    .await
+    .expect("judy → kim send");
+    let d = timeout(Duration::from_secs(5), inbox.recv())
+        .await
+        .expect("plain delivery timed out")
+        .expect("inbox bus closed unexpectedly");
+    let err = kim
+        .reply(&d.message, d.message.payload.clone())
+        .await
+        .expect_err("replying to a plain message must fail");
+    assert!(matches!(err, Error::NotARequest), "got {err:?}");
+
+    // Nobody answers this one.
+    let err = judy
+        .request::<ChatMessage, ChatMessage>(
+            "kim",
+            ChatMessage {
+                content: "anyone?".to_string(),
+            },
+            Duration::from_millis(300),
+        )
+        .await
+        .expect_err("an unanswered request must time out");
+    assert!(
+        matches!(err, Error::RequestTimeout { ref to, .. } if to == "kim"),
+        "expected RequestTimeout, got {err:?}"
+    );
+
+    coalition.shutdown().await;
+}
+
+/// **Dynamic join/leave.** `add_agent` brings a listen loop up with the same
+/// readiness guarantee as `new`; `remove_agent` drains only that agent, whose
+/// durable cursor lets a later re-join replay what it missed. The bus outlives
+/// membership changes and closes on shutdown.
+async fn scenario_dynamic_membership() {
+    let coalition = Coalition::<ChatMessage>::new(vec!["leo".to_string()])
+        .await
+        .expect("coalition creation");
+    let inbox = coalition.inbox();
+    let leo = coalition.agent("leo").await.expect("leo in coalition");
+
+    coalition.add_agent("mia").await.expect("mia joins");
+    leo.send(
+        "mia",
+        ChatMessage {
+            content: "welcome".to_string(),
+        },
+    )
+    .await
+    .expect("leo → mia send");
+    let d = timeout(Duration::from_secs(5), inbox.recv())
+        .await
+        .expect("delivery to a joined agent timed out")
+        .expect("inbox bus closed unexpectedly");
+    assert_eq!(d.recipient, "mia");
+
+    let err = coalition
+        .add_agent("mia")
+        .await
+        .expect_err("joining twice must fail");
+    assert!(matches!(err, Error::DuplicateAgent { .. }), "got {err:?}");
+
+    coalition.remove_agent("mia").await.expect("mia leaves");
+    assert!(coalition.agent("mia").await.is_none());
+    let err = coalition
+        .remove_agent("mia")
+        .await
+        .expect_err("leaving twice must fail");
+    assert!(matches!(err, Error::NotAMember { .. }), "got {err:?}");
+
+    // mia is gone but her record persists: the send is durable, not delivered.
+    leo.send(
+        "mia",
+        ChatMessage {
+            content: "while you were out".to_string(),
+        },
+    )
+    .await
+    .expect("leo → departed mia send");
+    assert!(
+        timeout(Duration::from_millis(500), inbox.recv())
+            .await
+            .is_err(),
+        "a removed agent must not receive deliveries"
+    );
+
+    coalition.add_agent("mia").await.expect("mia re-joins");
+    let d = timeout(Duration::from_secs(5), inbox.recv())
+        .await
+        .expect("re-joined agent did not replay its backlog")
+        .expect("inbox bus closed unexpectedly");
+    assert_eq!(d.recipient, "mia");
+    assert_eq!(
+        d.message.payload,
+        ChatMessage {
+            content: "while you were out".to_string()
+        }
+    );
+
+    coalition.shutdown().await;
+    let err = coalition
+        .add_agent("nina")
+        .await
+        .expect_err("joining a shut-down coalition must fail");
+    assert!(matches!(err, Error::CoalitionClosed), "got {err:?}");
+}
+
+/// **Per-agent inbox.** `inbox_for` yields only that recipient's deliveries,
+/// takes them off the shared bus, and a full per-agent inbox stalls only its
+/// own agent — deliveries to everyone else keep flowing.
+async fn scenario_per_agent_inbox() {
+    let options = CoalitionOptions {
+        agent_inbox_capacity: 1,
+        ..CoalitionOptions::default()
+    };
+    let coalition = Coalition::<ChatMessage>::new_with_options(
+        vec!["olga".to_string(), "pete".to_string(), "quinn".to_string()],
+        options,
+    )
+    .await
+    .expect("coalition creation");
+    let shared = coalition.inbox();
+    let pete_inbox = coalition.inbox_for("pete").await.expect("pete's inbox");
+    let olga = coalition.agent("olga").await.expect("olga in coalition");
+
+    // Overfill pete's capacity-1 inbox with nobody reading it.
+    for i in 0..3 {
+        olga.send(
+            "pete",
+            ChatMessage {
+                content: format!("p{i}"),
+            },
+        )
+        .await
+        .expect("olga → pete send");
+    }
+    olga.send(
+        "quinn",
+        ChatMessage {
+            content: "q".to_string(),
+        },
+    )
+    .await
+    .expect("olga → quinn send");
+
+    let d = timeout(Duration::from_secs(5), shared.recv())
+        .await
+        .expect("quinn's delivery was stalled by pete's full inbox")
+        .expect("inbox bus closed unexpectedly");
+    assert_eq!(d.recipient, "quinn");
+
+    for i in 0..3 {
+        let d = timeout(Duration::from_secs(5), pete_inbox.recv())
+            .await
+            .expect("pete's delivery timed out")
+            .expect("pete's inbox closed unexpectedly");
+        assert_eq!(d.recipient, "pete");
+        assert_eq!(d.message.payload.content, format!("p{i}"));
+    }
+    assert!(
+        timeout(Duration::from_millis(500), shared.recv())
+            .await
+            .is_err(),
+        "pete's deliveries must not reach the shared inbox"
+    );
+
+    let err = coalition
+        .inbox_for("ghost")
+        .await
+        .expect_err("no inbox for a non-member");
+    assert!(matches!(err, Error::NotAMember { .. }), "got {err:?}");
+
+    coalition.shutdown().await;
+    let closed = timeout(Duration::from_secs(5), pete_inbox.recv())
+        .await
+        .expect("recv did not resolve after shutdown");
+    assert!(closed.is_err(), "per-agent inbox must close after shutdown");
+}
+
+/// Test handler: answers every request with `re: <content>`.
+struct EchoHandler;
+
+impl AgentHandler<ChatMessage> for EchoHandler {
+    async fn handle(
+        &self,
+        ctx: AgentContext,
+        delivery: Delivery<ChatMessage>,
+    ) -> Result<(), HandlerError> {
+        let reply = ChatMessage {
+            content: format!("re: {}", delivery.message.payload.content),
+        };
+        ctx.reply(&delivery.message, reply).await?;
+        Ok(())
+    }
+}
+
+/// **Managed dispatch.** A registered `AgentHandler` receives the agent's
+/// deliveries (off the shared inbox) and can reply through its context; a
+/// second registration for the same agent is rejected.
+async fn scenario_handler_dispatch() {
+    let coalition = Coalition::<ChatMessage>::new(vec!["rosa".to_string(), "sam".to_string()])
+        .await
+        .expect("coalition creation");
+    let inbox = coalition.inbox();
+    coalition
+        .register_handler("sam", EchoHandler)
+        .await
+        .expect("register sam's handler");
+
+    let rosa = coalition.agent("rosa").await.expect("rosa in coalition");
+    for i in 0..3 {
+        let reply = rosa
+            .request::<ChatMessage, ChatMessage>(
+                "sam",
+                ChatMessage {
+                    content: format!("ping {i}"),
+                },
+                Duration::from_secs(5),
+            )
+            .await
+            .expect("sam's handler should answer");
+        assert_eq!(reply.payload.content, format!("re: ping {i}"));
+    }
+    assert!(
+        timeout(Duration::from_millis(500), inbox.recv())
+            .await
+            .is_err(),
+        "a handled agent's deliveries must not reach the shared inbox"
+    );
+
+    let err = coalition
+        .register_handler("sam", EchoHandler)
+        .await
+        .expect_err("a second handler must be rejected");
+    assert!(matches!(err, Error::HandlerExists { .. }), "got {err:?}");
+
+    coalition.shutdown().await;
+}
+
+/// **One reader, per-agent cursors.** The coalition's single changefeed
+/// reader scans from the lowest registered cursor. An agent that rejoins with
+/// an older cursor replays its own backlog, while agents already past that
+/// point are not handed it again.
+async fn scenario_shared_reader() {
+    let coalition = Coalition::<ChatMessage>::new(vec!["tina".to_string(), "uma".to_string()])
+        .await
+        .expect("coalition creation");
+    let inbox = coalition.inbox();
+    let tina = coalition.agent("tina").await.expect("tina in coalition");
+
+    coalition.remove_agent("uma").await.expect("uma leaves");
+    for i in 0..2 {
+        tina.send(
+            "uma",
+            ChatMessage {
+                content: format!("u{i}"),
+            },
+        )
+        .await
+        .expect("tina → uma send");
+    }
+    tina.send(
+        "tina",
+        ChatMessage {
+            content: "self".to_string(),
+        },
+    )
+    .await
+    .expect("tina → tina send");
+    let d = timeout(Duration::from_secs(5), inbox.recv())
+        .await
+        .expect("tina's delivery timed out")
+        .expect("inbox bus closed unexpectedly");
+    assert_eq!(d.recipient, "tina");
+
+    // uma rejoins behind tina's cursor: the rescan serves uma only.
+    coalition.add_agent("uma").await.expect("uma rejoins");
+    for i in 0..2 {
+        let d = timeout(Duration::from_secs(5), inbox.recv())
+            .await
+            .expect("uma's backlog was not replayed")
+            .expect("inbox bus closed unexpectedly");
+        assert_eq!(d.recipient, "uma");
+        assert_eq!(d.message.payload.content, format!("u{i}"));
+    }
+    assert!(
+        timeout(Duration::from_millis(500), inbox.recv())
+            .await
+            .is_err(),
+        "tina's message must not be handed out a second time"
+    );
+
+    coalition.shutdown().await;
+}
+
+/// A payload shape the `ChatMessage` coalition cannot decode.
+#[derive(Debug, Clone, Serialize, Deserialize, SurrealValue, PartialEq)]
+struct Reading {
+    celsius: i64,
+}
+
+/// Poll until `agent` has at least `n` dead letters.
+async fn dead_letters_for(client: &Client, agent: &str, n: usize) -> Vec<DeadLetter> {
+    timeout(Duration::from_secs(5), async {
+        loop {
+            let letters = dead_letter::list(client, Some(agent))
+                .await
+                .expect("list dead letters");
+            if letters.len() >= n {
+                return letters;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("dead letters did not appear in time")
+}
+
+/// **Dead letters.** An undecodable payload and a delivery that exhausts
+/// `max_delivery_attempts` both land in `dead_letter` instead of vanishing or
+/// looping; a dead letter re-driven once a consumer can decode it is delivered
+/// through the normal durable path.
+async fn scenario_dead_letter() {
+    let options = CoalitionOptions {
+        ack_mode: AckMode::Manual {
+            visibility_timeout: Duration::from_secs(30),
+        },
+        max_delivery_attempts: Some(1),
+        ..CoalitionOptions::default()
+    };
+    let c1 = Coalition::<ChatMessage>::new_with_options(
+        vec!["vic".to_string(), "wes".to_string()],
+        options,
+    )
+    .await
+    .expect("coalition creation");
+    let inbox = c1.inbox();
+    let vic = c1.agent("vic").await.expect("vic in coalition");
+
+    vic.send("wes", Reading { celsius: 21 })
+        .await
+        .expect("vic → wes send");
+    let letters = dead_letters_for(c1.client(), "wes", 1).await;
+    assert_eq!(letters[0].agent, "wes");
+    assert!(letters[0].versionstamp > 0);
+    let undecodable = letters[0].id.clone();
+
+    vic.send(
+        "wes",
+        ChatMessage {
+            content: "fail me".to_string(),
+        },
+    )
+    .await
+    .expect("vic → wes send");
+    let d = timeout(Duration::from_secs(5), inbox.recv())
+        .await
+        .expect("first delivery timed out")
+        .expect("inbox bus closed unexpectedly");
+    d.nack();
+    let letters = dead_letters_for(c1.client(), "wes", 2).await;
+    assert!(
+        letters[1].reason.contains("not acknowledged"),
+        "got {:?}",
+        letters[1].reason
+    );
+    assert!(
+        timeout(Duration::from_millis(500), inbox.recv())
+            .await
+            .is_err(),
+        "an exhausted delivery must not be redelivered"
+    );
+    c1.shutdown().await;
+
+    // A consumer that does understand the payload gets the re-driven letter.
+    let c2 = Coalition::<Reading>::new(vec!["wes".to_string()])
+        .await
+        .expect("reading coalition");
+    let inbox = c2.inbox();
+    dead_letter::redrive(c2.client(), &undecodable)
+        .await
+        .expect("re-drive the undecodable letter");
+    let d = timeout(Duration::from_secs(5), inbox.recv())
+        .await
+        .expect("re-driven letter was not delivered")
+        .expect("inbox bus closed unexpectedly");
+    assert_eq!(d.message.payload, Reading { celsius: 21 });
+    assert!(
+        dead_letter::get(c2.client(), &undecodable)
+            .await
+            .expect("get dead letter")
+            .is_none(),
+        "a re-driven letter is removed"
+    );
+    let err = dead_letter::redrive(c2.client(), &undecodable)
+        .await
+        .expect_err("re-driving twice must fail");
+    assert!(
+        matches!(err, Error::UnknownDeadLetter { .. }),
+        "got {err:?}"
+    );
+    c2.shutdown().await;
+}
+
+/// Next delivery on `inbox` as `(recipient, topic, content)`.
+async fn next_publication(
+    inbox: &AsyncReceiver<Delivery<ChatMessage>>,
+) -> (String, Option<String>, String) {
+    let d = timeout(Duration::from_secs(5), inbox.recv())
+        .await
+        .expect("publication was not delivered")
+        .expect("inbox bus closed unexpectedly");
+    (d.recipient, d.topic, d.message.payload.content)
+}
+
+/// **Topics.** One `publish` reaches every subscriber of the topic; a late
+/// subscriber replays the retained publications unless it subscribes from
+/// the latest, a publish with no other traffic still wakes the reader, an
+/// unsubscribed agent stops receiving, and subscribing twice is a no-op.
+async fn scenario_topics() {
+    let coalition = Coalition::<ChatMessage>::new(vec![
+        "xena".to_string(),
+        "yuri".to_string(),
+        "zoe".to_string(),
+    ])
+    .await
+    .expect("coalition creation");
+    let inbox = coalition.inbox();
+    let xena = coalition.agent("xena").await.expect("xena in coalition");
+    let post = |content: &str| ChatMessage {
+        content: content.to_string(),
+    };
+
+    coalition
+        .subscribe("yuri", "alerts")
+        .await
+        .expect("yuri subscribes");
+    xena.publish("alerts", post("a1"))
+        .await
+        .expect("publish a1");
+    assert_eq!(
+        next_publication(&inbox).await,
+        (
+            "yuri".to_string(),
+            Some("alerts".to_string()),
+            "a1".to_string()
+        )
+    );
+
+    // zoe subscribes late and replays a1; xena subscribes from the latest
+    // publication and does not.
+    coalition
+        .subscribe("zoe", "alerts")
+        .await
+        .expect("zoe subscribes");
+    assert_eq!(
+        next_publication(&inbox).await,
+        (
+            "zoe".to_string(),
+            Some("alerts".to_string()),
+            "a1".to_string()
+        ),
+        "a late subscriber replays retained publications"
+    );
+    coalition
+        .subscribe_from("xena", "alerts", SubscribeFrom::Latest)
+        .await
+        .expect("xena subscribes from the latest publication");
+    assert!(
+        timeout(Duration::from_millis(500), inbox.recv())
+            .await
+            .is_err(),
+        "SubscribeFrom::Latest must not replay earlier publications"
+    );
+    coalition
+        .subscribe("zoe", "alerts")
+        .await
+        .expect("subscribing twice is a no-op");
+
+    // The reader has been idle since zoe's subscription, and the no-op
+    // re-subscribe schedules no scan: only the publish itself can wake it.
+    xena.publish("alerts", post("a2"))
+        .await
+        .expect("publish a2");
+    let mut got = vec![
+        next_publication(&inbox).await,
+        next_publication(&inbox).await,
+        next_publication(&inbox).await,
+    ];
+    got.sort();
+    assert_eq!(got[0].0, "xena");
+    assert_eq!(got[1].0, "yuri");
+    assert_eq!(got[2].0, "zoe");
+    assert!(got.iter().all(|(_, _, content)| content == "a2"));
+
+    for name in ["xena", "zoe"] {
+        coalition
+            .unsubscribe(name, "alerts")
+            .await
+            .expect("unsubscribe");
+    }
+    xena.publish("alerts", post("a3"))
+        .await
+        .expect("publish a3");
+    assert_eq!(next_publication(&inbox).await.0, "yuri");
+    assert!(
+        timeout(Duration::from_millis(500), inbox.recv())
+            .await
+            .is_err(),
+        "nothing else may be delivered"
+    );
+
+    let err = xena
+        .publish("bad topic", post("x"))
+        .await
+        .expect_err("invalid topic name must be rejected");
+    assert!(matches!(err, Error::InvalidTopicName { .. }), "got {err:?}");
+    let err = coalition
+        .subscribe("nobody", "alerts")
+        .await
+        .expect_err("non-member must be rejected");
+    assert!(matches!(err, Error::NotAMember { .. }), "got {err:?}");
+
+    coalition.shutdown().await;
+}
+
+/// **Topic acks.** Under [`AckMode::Manual`] a subscription's cursor only
+/// moves past acknowledged publications: a nack is redelivered with the topic
+/// set, an unacked publication is replayed after a restart, and an acked one
+/// is not.
+async fn scenario_topic_manual_ack() {
+    let options = CoalitionOptions {
+        ack_mode: AckMode::Manual {
+            visibility_timeout: Duration::from_secs(30),
+        },
+        ..CoalitionOptions::default()
+    };
+    let names = || vec!["rhea".to_string(), "pavel".to_string()];
+    let post = ChatMessage {
+        content: "b1".to_string(),
+    };
+
+    let c1 = Coalition::<ChatMessage>::new_with_options(names(), options.clone())
+        .await
+        .expect("round-1 coalition");
+    let inbox = c1.inbox();
+    c1.subscribe("pavel", "bulletin")
+        .await
+        .expect("pavel subscribes");
+    let rhea = c1.agent("rhea").await.expect("rhea in coalition");
+    rhea.publish("bulletin", post.clone())
+        .await
+        .expect("publish b1");
+
+    let d = timeout(Duration::from_secs(5), inbox.recv())
+        .await
+        .expect("first delivery timed out")
+        .expect("inbox bus closed unexpectedly");
+    assert_eq!(d.topic.as_deref(), Some("bulletin"));
+    assert_eq!(d.attempt, 1);
+    d.nack();
+    let d = timeout(Duration::from_secs(5), inbox.recv())
+        .await
+        .expect("nacked publication was not redelivered")
+        .expect("inbox bus closed unexpectedly");
+    assert_eq!(d.topic.as_deref(), Some("bulletin"));
+    assert_eq!(d.attempt, 2);
+    // Crash-equivalent: shut down without acking.
+    c1.shutdown().await;
+
+    let c2 = Coalition::<ChatMessage>::new_with_options(names(), options.clone())
+        .await
+        .expect("round-2 coalition (restart)");
+    let inbox = c2.inbox();
+    let d = timeout(Duration::from_secs(5), inbox.recv())
+        .await
+        .expect("unacked publication was not replayed on restart")
+        .expect("inbox bus closed unexpectedly");
+    assert_eq!(d.recipient, "pavel");
+    assert_eq!(d.topic.as_deref(), Some("bulletin"));
+    assert_eq!(d.message.payload, post);
+    d.ack();
+    c2.shutdown().await;
+
+    let c3 = Coalition::<ChatMessage>::new_with_options(names(), options)
+        .await
+        .expect("round-3 coalition (restart)");
+    let inbox = c3.inbox();
+    assert!(
+        timeout(Duration::from_secs(1), inbox.recv()).await.is_err(),
+        "an acked publication must not be replayed"
+    );
+    c3.shutdown().await;
+}
+
+/// **Explicit clients.** A coalition built on its own [`Client`] (here a
+/// second database) is independent of one on the process-wide connection,
+/// even with the same agent names.
+async fn scenario_explicit_client() {
+    let mut settings = base_settings().await;
+    settings.sdb.database = "tenant_b".to_string();
+    let tenant_b = Client::connect(settings)
+        .await
+        .expect("connect to the second database");
+
+    let shared = Coalition::<ChatMessage>::new(vec!["tenant_agent".to_string()])
+        .await
+        .expect("coalition on the global client");
+    let own = Coalition::<ChatMessage>::new_with_client(
+        vec!["tenant_agent".to_string()],
+        tenant_b,
+        CoalitionOptions::default(),
+    )
+    .await
+    .expect("coalition on an explicit client");
+    assert_eq!(own.client().settings().sdb.database, "tenant_b");
+
+    let agent = own.agent("tenant_agent").await.expect("agent in coalition");
+    assert_eq!(agent.client().settings().sdb.database, "tenant_b");
+    agent
+        .send(
+            "tenant_agent",
+            ChatMessage {
+                content: "tenant b only".to_string(),
+            },
+        )
+        .await
+        .expect("send within tenant b");
+
+    let d = timeout(Duration::from_secs(5), own.inbox().recv())
+        .await
+        .expect("tenant b delivery timed out")
+        .expect("inbox bus closed unexpectedly");
+    assert_eq!(d.message.payload.content, "tenant b only");
+    assert!(
+        timeout(Duration::from_millis(500), shared.inbox().recv())
+            .await
+            .is_err(),
+        "a message in tenant b must not reach the global database's coalition"
+    );
+
+    own.shutdown().await;
+    shared.shutdown().await;
+}
+
+/// Settings of the running test database, to derive per-scenario variants
+/// from. Unlike `SETTINGS`, the endpoint is the one actually connected to,
+/// which differs under `docker.dynamic_port`.
+async fn base_settings() -> Settings {
+    Client::global()
+        .await
+        .expect("global client")
+        .settings()
+        .clone()
+}
+
+/// **Fallible connection setup.** An unreachable endpoint is retried
+/// `connect_attempts` times and then returned as [`Error::Connect`] instead of
+/// panicking.
+async fn scenario_connect_failure() {
+    let mut settings = base_settings().await;
+    settings.sdb.endpoint = "ws://127.0.0.1:1".to_string();
+    settings.sdb.connect_attempts = 2;
+    settings.sdb.connect_backoff_ms = 10;
+    let err = timeout(Duration::from_secs(10), Client::connect(settings))
+        .await
+        .expect("connect must give up in time")
+        .expect_err("an unreachable endpoint must fail");
+    assert!(matches!(err, Error::Connect(_)), "got {err:?}");
+}
+
+/// **Parallel runs.** Two containers started from the same settings under
+/// `docker.dynamic_port` get distinct names and host ports, and each endpoint
+/// reaches its own database. Needs Docker, so not embedded or production.
+async fn scenario_parallel_containers() {
+    if SETTINGS.sdb.is_embedded() || SETTINGS.environment == "production" {
+        return;
+    }
+    let mut settings = SETTINGS.clone();
+    settings.sdb.container_name = format!("{}_parallel", settings.sdb.container_name);
+    settings.docker.dynamic_port = true;
+    settings.docker.keep_container = false;
+    settings.docker.attach = false;
+
+    let tasks = TaskTracker::new();
+    let token = CancellationToken::new();
+    let mut containers = Vec::new();
+    for _ in 0..2 {
+        let mut container = SurrealDBContainer::new(settings.clone(), &tasks, &token)
+            .await
+            .expect("docker client");
+        container
+            .start_and_wait()
+            .await
+            .expect("container becomes healthy");
+        containers.push(container);
+    }
+    let (a, b) = (containers[0].settings(), containers[1].settings());
+    assert_ne!(a.sdb.container_name, b.sdb.container_name);
+    assert_ne!(
+        a.sdb.endpoint, b.sdb.endpoint,
+        "each run is published on its own host port"
+    );
+    for container in &containers {
+        Client::connect(container.settings().clone())
+            .await
+            .expect("connect to the published port")
+            .check_ready()
+            .await
+            .expect("the container answers");
+    }
+    for container in containers {
+        container.stop().await.expect("stop container");
+    }
+    tasks.close();
+    timeout(Duration::from_secs(10), tasks.wait())
+        .await
+        .expect("stopped containers leave no tasks behind");
+}
+
+/// **Non-root authentication.** A database-level user signs in and can run
+/// the bus (schema, agents) without root; a wrong password is
+/// [`Error::Auth`]. Embedded engines have no users, so this needs a server.
+async fn scenario_database_user(db: &Surreal<any::Any>) {
+    if SETTINGS.sdb.is_embedded() {
+        return;
+    }
+    db.query("DEFINE USER OVERWRITE bus_editor ON DATABASE PASSWORD 'bus_editor' ROLES EDITOR")
+        .await
+        .and_then(|r| r.check())
+        .expect("define database user");
+
+    let mut settings = base_settings().await;
+    settings.sdb.auth = Auth::Database {
+        username: "bus_editor".to_string(),
+        password: "bus_editor".to_string(),
+    };
+    let client = Client::connect(settings.clone())
+        .await
+        .expect("database user signs in");
+    let agent = Agent::new_with_client("db_user_agent", &client)
+        .await
+        .expect("database user creates agents");
+    assert_eq!(
+        agent.client().settings().sdb.database,
+        SETTINGS.sdb.database
+    );
+
+    settings.sdb.auth = Auth::Database {
+        username: "bus_editor".to_string(),
+        password: "wrong".to_string(),
+    };
+    settings.sdb.connect_attempts = 1;
+    let err = Client::connect(settings)
+        .await
+        .expect_err("a wrong password must be rejected");
+    assert!(matches!(err, Error::Auth(_)), "got {err:?}");
+}
+
+/// **Per-agent identities.** On a secured schema an agent signed in as its
+/// own record can send as itself, but cannot forge a sender, read other
+/// agents' messages or move their cursors. Needs a server, like
+/// [`scenario_database_user`].
+async fn scenario_secured_identities() {
+    if SETTINGS.sdb.is_embedded() {
+        return;
+    }
+    let mut settings = base_settings().await;
+    settings.sdb.database = "secured".to_string();
+    settings.sdb.secured = true;
+    let operator = Client::connect(settings.clone())
+        .await
+        .expect("operator connects and defines the secured schema");
+    assert!(
+        migrate::plan(&operator)
+            .await
+            .expect("plan")
+            .steps
+            .is_empty(),
+        "the secured migration is applied once, not on every connect"
+    );
+    let root = operator.db();
+    root.query("DELETE message; DELETE cursor;")
+        .await
+        .and_then(|r| r.check())
+        .expect("clear the secured database");
+    for name in ["sec_alice", "sec_bob", "sec_eve"] {
+        identity::provision(&operator, name, &format!("{name}_secret"))
+            .await
+            .expect("provision agent identity");
+    }
+
+    let connect_as = |name: &str| {
+        let mut settings = settings.clone();
+        settings.sdb.auth = identity::auth(name, &format!("{name}_secret"));
+        Client::connect(settings)
+    };
+    let alice = connect_as("sec_alice").await.expect("alice signs in");
+    let eve = connect_as("sec_eve").await.expect("eve signs in");
+
+    let alice_agent = Agent::new_with_client("sec_alice", &alice)
+        .await
+        .expect("alice's agent record is readable");
+    alice_agent
+        .send(
+            "sec_bob",
+            ChatMessage {
+                content: "for bob only".to_string(),
+            },
+        )
+        .await
+        .expect("alice sends as itself");
+
+    let eve_as_alice = Agent::new_with_client("sec_alice", &eve)
+        .await
+        .expect("alice's agent record is readable to eve");
+    let err = eve_as_alice
+        .send(
+            "sec_bob",
+            ChatMessage {
+                content: "forged".to_string(),
+            },
+        )
+        .await
+        .expect_err("eve must not send as alice");
+    assert!(matches!(err, Error::Send { .. }), "got {err:?}");
+
+    let forged = eve
+        .db()
+        .query("RELATE agent:sec_alice->message->agent:sec_bob SET created = time::now()")
+        .await
+        .and_then(|r| r.check());
+    let mut sent = root
+        .query("SELECT VALUE count() FROM message WHERE in = agent:sec_alice GROUP ALL")
+        .await
+        .expect("count alice's messages");
+    let sent: Option<i64> = sent.take(0).expect("message count");
+    assert_eq!(
+        sent,
+        Some(1),
+        "eve must not forge a message from alice ({forged:?})"
+    );
+
+    let mut visible = eve
+        .db()
+        .query("SELECT * FROM message")
+        .await
+        .expect("eve queries messages");
+    let visible: Vec<surrealdb_types::Value> = visible.take(0).expect("eve's messages");
+    assert!(
+        visible.is_empty(),
+        "eve must not read messages between others"
+    );
+
+    let _ = eve
+        .db()
+        .query("UPSERT cursor:sec_bob SET versionstamp = 999999")
+        .await;
+    let mut cursor = root
+        .query("SELECT VALUE versionstamp FROM ONLY cursor:sec_bob")
+        .await
+        .expect("read bob's cursor");
+    let cursor: Option<i64> = cursor.take(0).expect("bob's cursor");
+    assert_ne!(cursor, Some(999_999), "eve must not move bob's cursor");
+}
+
+/// **Schema migrations.** A connected database is at the latest version with
+/// nothing pending; a smaller retention window is planned as a kept
+/// changefeed unless shrinking is explicitly allowed.
+async fn scenario_migrations() {
+    let mut settings = base_settings().await;
+    settings.sdb.database = "migrations".to_string();
+    let client = Client::connect(settings.clone())
+        .await
+        .expect("connect and migrate");
+    let plan = migrate::plan(&client).await.expect("plan");
+    assert_eq!(plan.from_version, migrate::latest());
+    assert!(
+        plan.steps.is_empty(),
+        "nothing pending after connect: {plan:?}"
+    );
+
+    settings.sdb.message_retention_secs = 60;
+    settings.sdb.auto_migrate = false;
+    let shorter = Client::connect(settings.clone())
+        .await
+        .expect("connect without migrating");
+    let plan = migrate::plan(&shorter).await.expect("plan");
+    assert!(plan.is_current(), "a shrink is not applied by default");
+    assert!(
+        plan.steps.contains(&Step::KeepChangefeed {
+            table: MESSAGE_TABLE,
+            current: SETTINGS.sdb.message_retention_secs,
+            requested: 60,
+        }),
+        "got {plan:?}"
+    );
+
+    settings.sdb.allow_changefeed_shrink = true;
+    let shrink = Client::connect(settings).await.expect("connect");
+    let applied = migrate::apply(&shrink).await.expect("apply the shrink");
+    assert!(
+        applied.steps.contains(&Step::Changefeed {
+            table: MESSAGE_TABLE,
+            from: Some(SETTINGS.sdb.message_retention_secs),
+            to: 60,
+        }),
+        "got {applied:?}"
+    );
+    assert!(
+        migrate::plan(&shrink).await.expect("plan").steps.is_empty(),
+        "the shrink is applied once"
+    );
+
+    // Turning `sdb.secured` on later replays the secured migration.
+    let mut settings = base_settings().await;
+    settings.sdb.database = "migrations".to_string();
+    settings.sdb.secured = true;
+    settings.sdb.auto_migrate = false;
+    let secured = Client::connect(settings)
+        .await
+        .expect("connect without migrating");
+    let plan = migrate::plan(&secured).await.expect("plan");
+    let replayed: Vec<u32> = plan
+        .steps
+        .iter()
+        .filter_map(|step| match step {
+            Step::Migrate { version, .. } => Some(*version),
+            _ => None,
+        })
+        .collect();
+    let secured_versions: Vec<u32> = migrate::MIGRATIONS
+        .iter()
+        .filter(|m| m.secured)
+        .map(|m| m.version)
+        .collect();
+    assert_eq!(replayed, secured_versions, "got {plan:?}");
+}